@@ -0,0 +1,70 @@
+use crate::lexer::LexicalError;
+use rusty_firrtl::{ChirrtlMemoryReadUnderWrite, Identifier, Info, Int, Memory, MemoryPort, Type};
+use num_bigint::BigInt;
+use num_traits::Num;
+
+/// One line in the body of a `mem` declaration
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryField {
+    DataType(Type),
+    Depth(Int),
+    ReadLatency(Int),
+    WriteLatency(Int),
+    ReadUnderWrite(ChirrtlMemoryReadUnderWrite),
+    Port(MemoryPort),
+}
+
+/// Builds a `Memory` from the lines of its body, which may come in any order. Every
+/// field other than the ports has to be given exactly once, ports any number of times.
+pub fn build_memory(name: Identifier, fields: Vec<MemoryField>, info: Info) -> Result<Memory, LexicalError> {
+    fn set<T>(slot: &mut Option<T>, value: T, field: &str) -> Result<(), LexicalError> {
+        match slot.replace(value) {
+            Some(_) => Err(LexicalError::DuplicateMemoryField(field.to_string())),
+            None => Ok(()),
+        }
+    }
+    fn get<T>(slot: Option<T>, field: &str) -> Result<T, LexicalError> {
+        slot.ok_or_else(|| LexicalError::MissingMemoryField(field.to_string()))
+    }
+
+    let (mut tpe, mut depth, mut rlat, mut wlat, mut ruw) = (None, None, None, None, None);
+    let mut ports = vec![];
+    for field in fields {
+        match field {
+            MemoryField::DataType(x)       => set(&mut tpe, x, "data-type")?,
+            MemoryField::Depth(x)          => set(&mut depth, x, "depth")?,
+            MemoryField::ReadLatency(x)    => set(&mut rlat, x, "read-latency")?,
+            MemoryField::WriteLatency(x)   => set(&mut wlat, x, "write-latency")?,
+            MemoryField::ReadUnderWrite(x) => set(&mut ruw, x, "read-under-write")?,
+            MemoryField::Port(port)        => ports.push(Box::new(port)),
+        }
+    }
+    Ok(Memory::new(
+        name,
+        get(tpe, "data-type")?,
+        get(depth, "depth")?,
+        get(rlat, "read-latency")?,
+        get(wlat, "write-latency")?,
+        get(ruw, "read-under-write")?,
+        ports,
+        info))
+}
+
+/// Parses the legacy string integer literals such as `"h1f"`, `"b101"` or `"h-3"`
+pub fn parse_string_int(lit: &str) -> Option<Int> {
+    let lit = lit.trim_matches('"');
+    let radix = match lit.chars().next()? {
+        'b' => 2,
+        'o' => 8,
+        'd' => 10,
+        'h' => 16,
+        _ => return None,
+    };
+    let digits = &lit[1..];
+    let (sign, digits) = match digits.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", digits),
+    };
+    let value = BigInt::from_str_radix(digits, radix).ok()?;
+    Int::from_str(&format!("{}{}", sign, value)).ok()
+}
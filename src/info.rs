@@ -0,0 +1,116 @@
+use rusty_firrtl::Info;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// One source file position of an `Info`. Chisel compresses several columns on the
+/// same line into a single locator (`Foo.scala 40:{5,9}`), so there may be more than one column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocator {
+    pub file: String,
+    pub line: u32,
+    pub columns: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfoError {
+    /// A file without a line number following it
+    MissingPosition(String),
+    /// A position that is not of the form `line`, `line:col`, `line:{col,...}` or `{pos,...}`
+    InvalidPosition(String),
+}
+
+/// Splits an `Info` such as `@[src/a.scala 12:3 src/b.scala {40:{5,9},41:2}]` into one
+/// `SourceLocator` per line it refers to, in the order they appear.
+pub fn parse_info(info: &Info) -> Result<Vec<SourceLocator>, InfoError> {
+    parse_locators(&info.0)
+}
+
+/// Same as `parse_info`, but for the text between `@[` and `]`
+pub fn parse_locators(info: &str) -> Result<Vec<SourceLocator>, InfoError> {
+    let words = split_words(info);
+    let mut locators = vec![];
+    let mut words = words.into_iter();
+    while let Some(file) = words.next() {
+        let position = words.next()
+            .ok_or_else(|| InfoError::MissingPosition(file.clone()))?;
+        let mut chars = position.chars().peekable();
+        let positions = parse_positions(&mut chars)
+            .filter(|_| chars.peek().is_none())
+            .ok_or_else(|| InfoError::InvalidPosition(position.clone()))?;
+        for (line, columns) in positions {
+            locators.push(SourceLocator { file: file.clone(), line, columns });
+        }
+    }
+    Ok(locators)
+}
+
+// Whitespace separated words, where whitespace inside braces does not split
+fn split_words(info: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut depth = 0;
+    for c in info.chars() {
+        match c {
+            '{' => { depth += 1; word.push(c); }
+            '}' => { depth -= 1; word.push(c); }
+            c if c.is_whitespace() => {
+                if depth == 0 && !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            _ => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// `line`, `line:col`, `line:{col,col}` or `{position,position}`
+fn parse_positions(chars: &mut Peekable<Chars>) -> Option<Vec<(u32, Vec<u32>)>> {
+    if chars.peek() == Some(&'{') {
+        let mut positions = vec![];
+        for group in parse_group(chars, parse_positions)? {
+            positions.extend(group);
+        }
+        return Some(positions);
+    }
+
+    let line = parse_number(chars)?;
+    if chars.peek() != Some(&':') {
+        return Some(vec![(line, vec![])]);
+    }
+    chars.next();
+
+    let columns = if chars.peek() == Some(&'{') {
+        parse_group(chars, parse_number)?
+    } else {
+        vec![parse_number(chars)?]
+    };
+    Some(vec![(line, columns)])
+}
+
+// `{item,item,...}`
+fn parse_group<T>(chars: &mut Peekable<Chars>, item: fn(&mut Peekable<Chars>) -> Option<T>) -> Option<Vec<T>> {
+    if chars.next() != Some('{') {
+        return None;
+    }
+    let mut items = vec![item(chars)?];
+    loop {
+        match chars.next()? {
+            ',' => items.push(item(chars)?),
+            '}' => return Some(items),
+            _ => return None,
+        }
+    }
+}
+
+fn parse_number(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+        digits.push(*c);
+        chars.next();
+    }
+    digits.parse().ok()
+}
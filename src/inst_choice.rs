@@ -0,0 +1,62 @@
+use rusty_firrtl::{Circuit, CircuitModule, Identifier, Stmt, Stmts};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstChoiceError {
+    /// No `option` with this name is declared in the circuit
+    UndefinedOption(Identifier),
+    /// The `option` exists, but does not list this case
+    UndefinedCase(Identifier, Identifier),
+}
+
+/// Produce a copy of `circuit` where every `instchoice` over `option` is replaced by a plain
+/// instance of the module chosen for `case`, or of the default module when the
+/// `instchoice` has no entry for `case`. The `option` declaration itself is removed,
+/// `instchoice` statements over other options are kept as is.
+pub fn select_case(circuit: &Circuit, option: &Identifier, case: &Identifier) -> Result<Circuit, InstChoiceError> {
+    let group = circuit.modules.iter().find_map(|m| match m.as_ref() {
+        CircuitModule::OptionGroup(og) if og.name == *option => Some(og),
+        _ => None,
+    }).ok_or_else(|| InstChoiceError::UndefinedOption(option.clone()))?;
+
+    if !group.cases.iter().any(|c| c.name == *case) {
+        return Err(InstChoiceError::UndefinedCase(option.clone(), case.clone()));
+    }
+
+    let mut selected = circuit.clone();
+    selected.modules.retain(|m| !matches!(m.as_ref(), CircuitModule::OptionGroup(og) if og.name == *option));
+    for module in selected.modules.iter_mut() {
+        if let CircuitModule::Module(m) = module.as_mut() {
+            select_in_stmts(&mut m.stmts, option, case);
+        }
+    }
+    Ok(selected)
+}
+
+fn select_in_stmts(stmts: &mut Stmts, option: &Identifier, case: &Identifier) {
+    for stmt in stmts.iter_mut() {
+        match stmt.as_mut() {
+            Stmt::InstChoice(inst, default, opt, cases, info) if opt == option => {
+                let module = cases.iter()
+                    .find(|c| c.case == *case)
+                    .map(|c| c.module.clone())
+                    .unwrap_or_else(|| default.clone());
+                *stmt.as_mut() = Stmt::Inst(inst.clone(), module, info.clone());
+            }
+            Stmt::When(_, _, stmts_true, stmts_false) => {
+                select_in_stmts(stmts_true, option, case);
+                if let Some(stmts_false) = stmts_false {
+                    select_in_stmts(stmts_false, option, case);
+                }
+            }
+            Stmt::Match(_, _, arms) => {
+                for arm in arms.iter_mut() {
+                    select_in_stmts(&mut arm.stmts, option, case);
+                }
+            }
+            Stmt::LayerBlock(_, stmts, _) => {
+                select_in_stmts(stmts, option, case);
+            }
+            _ => { }
+        }
+    }
+}
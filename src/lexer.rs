@@ -1,11 +1,21 @@
-use rusty_firrtl::Int;
+use rusty_firrtl::{Int, Version};
 use logos::{Lexer, Logos};
 use std::collections::VecDeque;
+use std::fmt;
 use std::num::ParseIntError;
 
 #[derive(Default, Debug, Clone, PartialEq)]
 pub enum LexicalError {
     InvalidInteger(ParseIntError),
+    InvalidRadixInteger(String),
+    InvalidStringInteger(String),
+    InvalidReadUnderWrite(String),
+    InvalidLayerConvention(String),
+    InvalidBoolean(String),
+    InvalidDouble(String),
+    MissingMemoryField(String),
+    DuplicateMemoryField(String),
+    UnsupportedVersion(VersionError),
     #[default]
     InvalidToken,
 }
@@ -16,6 +26,25 @@ impl From<ParseIntError> for LexicalError {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionError {
+    /// The construct was introduced after the declared version
+    Requires(String, Version),
+    /// The construct only exists before the given version
+    Removed(String, Version),
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VersionError::Requires(construct, Version(a, b, c)) =>
+                write!(f, "construct `{}` requires FIRRTL >= {}.{}.{}", construct, a, b, c),
+            VersionError::Removed(construct, Version(a, b, c)) =>
+                write!(f, "construct `{}` was removed in FIRRTL {}.{}.{}", construct, a, b, c),
+        }
+    }
+}
+
 #[derive(Logos, Debug, Clone, PartialEq)]
 pub enum Token {
     EOF,
@@ -24,6 +53,8 @@ pub enum Token {
     Info(String),
     Annotations(String),
     ID(Int),
+    EnumColon,
+    DoubleLiteral(String),
 
     #[token(" ")]
     Space,
@@ -46,6 +77,12 @@ pub enum Token {
     #[regex(r#""([^"\\]|\\.)*""#, |lex| lex.slice().to_string())]
     String(String),
 
+    #[regex(r"'([^'\\\n]|\\.)*'", |lex| lex.slice().to_string())]
+    RawString(String),
+
+    #[regex(";[^\n]*", |lex| lex.slice()[1..].to_string())]
+    Comment(String),
+
     #[token("/")]
     Slash,
 
@@ -67,6 +104,12 @@ pub enum Token {
     #[token("}")]
     RightBracket,
 
+    #[token("{|")]
+    LeftEnumBracket,
+
+    #[token("|}")]
+    RightEnumBracket,
+
     #[token("(")]
     LeftParenthesis,
 
@@ -112,6 +155,12 @@ pub enum Token {
     #[token("Probe")]
     Probe,
 
+    #[token("rwprobe")]
+    RWProbeType,
+
+    #[token("RWProbe")]
+    RWProbe,
+
     #[token("Analog")]
     Analog,
 
@@ -121,6 +170,36 @@ pub enum Token {
     #[token("flip")]
     Flip,
 
+    #[token("Integer")]
+    IntegerType,
+
+    #[token("String")]
+    StringType,
+
+    #[token("Boolean")]
+    BooleanType,
+
+    #[token("Double")]
+    DoubleType,
+
+    #[token("Path")]
+    PathType,
+
+    #[token("List")]
+    ListType,
+
+    #[token("AnyRef")]
+    AnyRefType,
+
+    #[token("Inst")]
+    InstType,
+
+    #[token("path")]
+    Path,
+
+    #[regex("integer_add|integer_mul|integer_shr|integer_shl|list_concat", |lex| lex.slice().to_string())]
+    PropPrimOp(String),
+
     #[regex("add|sub|mul|div|rem|lt|leq|gt|geq|eq|neq|dshl|dshr|and|or|xor|cat", |lex| lex.slice().to_string())]
     E2Op(String),
 
@@ -139,8 +218,8 @@ pub enum Token {
     #[token("validif")]
     ValidIf,
 
-// #[token("mem")]
-// Mem,
+    #[token("mem")]
+    Mem,
 
     #[token("smem")]
     SMem,
@@ -199,6 +278,12 @@ pub enum Token {
     #[token("of")]
     Of,
 
+    #[token("instchoice")]
+    InstChoice,
+
+    #[token("option")]
+    Option,
+
     #[token("node")]
     Node,
 
@@ -211,6 +296,9 @@ pub enum Token {
     #[token("when")]
     When,
 
+    #[token("match")]
+    Match,
+
     #[token("else")]
     Else,
 
@@ -223,9 +311,27 @@ pub enum Token {
     #[token("assert")]
     Assert,
 
+    #[token("assume")]
+    Assume,
+
+    #[token("cover")]
+    Cover,
+
     #[token("skip")]
     Skip,
 
+    #[token("force")]
+    Force,
+
+    #[token("force_initial")]
+    ForceInitial,
+
+    #[token("release")]
+    Release,
+
+    #[token("release_initial")]
+    ReleaseInitial,
+
     #[token("input")]
     Input,
 
@@ -250,6 +356,18 @@ pub enum Token {
     #[token("intrinsic")]
     Intrinsic,
 
+    #[token("class")]
+    Class,
+
+    #[token("extclass")]
+    ExtClass,
+
+    #[token("object")]
+    Object,
+
+    #[token("propassign")]
+    PropAssign,
+
     #[token("FIRRTL")]
     FIRRTL,
 
@@ -262,6 +380,21 @@ pub enum Token {
     #[token("connect")]
     Connect,
 
+    #[token("<=")]
+    LegacyConnect,
+
+    #[token("<-")]
+    LegacyPartialConnect,
+
+    #[token("is")]
+    Is,
+
+    #[token("invalid")]
+    Invalid,
+
+    #[token("with")]
+    With,
+
     #[token("public")]
     Public,
 
@@ -271,16 +404,51 @@ pub enum Token {
     #[token("const")]
     Const,
 
+    #[token("type")]
+    Type,
+
+    #[token("layer")]
+    Layer,
+
+    #[token("layerblock")]
+    LayerBlock,
+
+    #[token("enablelayer")]
+    EnableLayer,
+
     #[regex(r"[.,:=@%<>()\[\]{}]", |lex| lex.slice().to_string())]
     Symbol(String),
 
     #[token(".")]
     Period,
 
+    #[token("=>")]
+    FatArrow,
+
     #[error]
     Error
 }
 
+/// Which flavor of FIRRTL syntax the lexer should produce tokens for
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum SyntaxMode {
+    /// FIRRTL 3.x and later (`connect`, `invalidate`, `regreset`)
+    #[default]
+    Standard,
+
+    /// Also accepts the FIRRTL 1.x/2.x forms (`<=`, `<-`, `is invalid`, `reg ... with`)
+    Legacy,
+}
+
+/// A `;` line comment, which is never handed to the parser
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// Everything after the `;` up to the end of the line
+    pub text: String,
+    pub line: usize,
+    pub start: usize,
+}
+
 #[derive(Default, Debug, Clone)]
 enum LexerMode {
     #[default]
@@ -289,6 +457,7 @@ enum LexerMode {
     Info,
     DotId,
     Anno,
+    Double,
     Normal,
 }
 
@@ -327,15 +496,26 @@ pub struct FIRRTLLexer<'input> {
     lexer: Lexer<'input, Token>,
     tokens: VecDeque<TokenString>,
     mode: LexerMode,
+    syntax: SyntaxMode,
     indent_levels: Vec<u32>,
     cur_indent: u32,
     info_string: String,
     anno_string: String,
+    backtick_string: String,
+    double_string: String,
     previous_right_square: bool,
     angle_num: u32,
     square_num: u32,
     bracket_num: u32,
     parenthesis_num: u32,
+    enum_brackets: Vec<u32>,
+    // Last token handed to the parser, and whether the next one is the first on its line.
+    // Used to tell keywords apart from identifiers that happen to be spelled the same.
+    last_token: Option<Token>,
+    line_start: bool,
+    statement: Option<Token>,
+    comments: Option<Vec<Comment>>,
+    keep_legacy_connects: bool,
     returned_eof: bool,
     lineno: usize,
 }
@@ -344,24 +524,58 @@ impl<'input> FIRRTLLexer<'input> {
     const TAB_WIDTH: u32 = 2;
 
     pub fn new(input: &'input str) -> Self {
+        Self::new_with_mode(input, SyntaxMode::Standard)
+    }
+
+    pub fn new_with_mode(input: &'input str, syntax: SyntaxMode) -> Self {
         Self {
             lexer: Token::lexer(input),
             tokens: VecDeque::new(),
             indent_levels: vec![0],
             mode: LexerMode::Indent,
+            syntax,
             cur_indent: 0,
             info_string: String::default(),
             anno_string: String::default(),
+            backtick_string: String::default(),
+            double_string: String::default(),
             previous_right_square: false,
             angle_num: 0,
             square_num: 0,
             bracket_num: 0,
             parenthesis_num: 0,
+            enum_brackets: vec![],
+            last_token: None,
+            line_start: true,
+            statement: None,
+            comments: None,
+            keep_legacy_connects: false,
             returned_eof: false,
             lineno: 1,
         }
     }
 
+    /// Keep the comments that are skipped while lexing, so that they can be retrieved by `take_comments`
+    pub fn retain_comments(mut self) -> Self {
+        self.comments = Some(vec![]);
+        self
+    }
+
+    /// Hand `<=` and `<-` to the parser in `SyntaxMode::Standard` as well, so that a version check
+    /// can report them as removed instead of as invalid tokens
+    pub fn keep_legacy_connects(mut self) -> Self {
+        self.keep_legacy_connects = true;
+        self
+    }
+
+    /// Comments seen so far, in source order. Always empty unless `retain_comments` was called.
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        match self.comments.as_mut() {
+            Some(comments) => std::mem::take(comments),
+            None => vec![],
+        }
+    }
+
     fn indent_mode(&mut self) -> Option<TokenString> {
         let ts = self.tokens.pop_front().unwrap();
         match ts.token {
@@ -378,6 +592,10 @@ impl<'input> FIRRTLLexer<'input> {
                 self.cur_indent = 0;
                 None
             }
+            Token::Comment(text) => {
+                self.retain_comment(text, ts.start);
+                None
+            }
             _ => {
                 let start = ts.start;
                 self.tokens.push_front(ts);
@@ -409,6 +627,10 @@ impl<'input> FIRRTLLexer<'input> {
                 self.mode = LexerMode::Normal;
                 Some(TokenString::from((Token::Info(self.info_string.clone()), ts.line, ts.start)))
             }
+            Token::Comment(_) => {
+                self.relex_comment(ts);
+                None
+            }
             _ => {
                 self.info_string.push_str(&ts.name.unwrap());
                 None
@@ -429,7 +651,12 @@ impl<'input> FIRRTLLexer<'input> {
             }
             _ => {
                 self.mode = LexerMode::Normal;
-                Some(ts)
+                if Self::is_keyword(&ts) {
+                    // Field access, e.g. `io.wire` or `x.reset`
+                    Some(TokenString::from((Token::Identifier(ts.name.unwrap()), ts.line, ts.start)))
+                } else {
+                    Some(ts)
+                }
             }
         }
     }
@@ -437,16 +664,36 @@ impl<'input> FIRRTLLexer<'input> {
     fn intid_mode(&mut self) -> Option<TokenString> {
         let ts = self.tokens.pop_front().unwrap();
         match ts.token {
-            Token::IntegerDec(x) => {
-                Some(TokenString::from((Token::ID(x), ts.line, ts.start)))
-            }
             Token::Backtick => {
                 self.mode = LexerMode::Normal;
+                let id = std::mem::take(&mut self.backtick_string);
+                let is_number = id.bytes().all(|b| b.is_ascii_digit()) && (id == "0" || !id.starts_with('0'));
+                let token = if id.is_empty() {
+                    Token::Error
+                } else if is_number {
+                    // `0`, `12` as used for numeric bundle fields
+                    Token::ID(Int::from_str(&id).unwrap())
+                } else {
+                    // Any other text, e.g. `foo bar` or `0abc`
+                    Token::Identifier(id)
+                };
+                Some(TokenString::from((token, ts.line, ts.start)))
+            }
+            Token::Newline => {
+                // Unterminated escape
+                self.mode = LexerMode::Normal;
+                std::mem::take(&mut self.backtick_string);
+                let (line, start) = (ts.line, ts.start);
+                self.tokens.push_front(ts);
+                Some(TokenString::from((Token::Error, line, start)))
+            }
+            Token::Comment(_) => {
+                self.relex_comment(ts);
                 None
             }
             _ => {
-                println!("{:?}", ts);
-                Some(TokenString::from((Token::Error, ts.line, ts.start)))
+                self.backtick_string.push_str(&ts.name.unwrap_or_default());
+                None
             }
         }
     }
@@ -464,6 +711,10 @@ impl<'input> FIRRTLLexer<'input> {
                     None
                 }
             }
+            Token::Comment(_) => {
+                self.relex_comment(ts);
+                None
+            }
             _ => {
                 self.previous_right_square = false;
                 self.anno_string.push_str(&ts.name.unwrap());
@@ -472,6 +723,46 @@ impl<'input> FIRRTLLexer<'input> {
         }
     }
 
+    fn retain_comment(&mut self, text: String, start: usize) {
+        if let Some(comments) = self.comments.as_mut() {
+            comments.push(Comment { text, line: self.lineno, start });
+        }
+    }
+
+    // `;` only starts a comment in normal and indent mode. Anywhere else, e.g. in `a;b`
+    // or an info, it is plain text and the rest of the line is lexed again.
+    fn relex_comment(&mut self, ts: TokenString) {
+        let text = ts.name.unwrap();
+        let mut lexer = Token::lexer(&text[1..]);
+        let mut tokens = vec![TokenString::new(Token::Symbol(";".to_string()), ts.line, ts.start, ";".to_string())];
+        while let Some(token) = lexer.next() {
+            tokens.push(TokenString::new(token, ts.line, ts.start + 1 + lexer.span().start, lexer.slice().to_string()));
+        }
+        for token in tokens.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+    }
+
+    // The text of a `Double(...)` literal, e.g. `-1.5` or `2.5e3`, which is not made of
+    // tokens that the parser could put back together without losing the sign or leading zeros
+    fn double_mode(&mut self) -> Option<TokenString> {
+        let ts = self.tokens.pop_front().unwrap();
+        match ts.token {
+            Token::Space | Token::Tab => None,
+            Token::RightParenthesis | Token::Newline => {
+                self.mode = LexerMode::Normal;
+                let (line, start) = (ts.line, ts.start);
+                self.tokens.push_front(ts);
+                let text = std::mem::take(&mut self.double_string);
+                Some(TokenString::from((Token::DoubleLiteral(text), line, start)))
+            }
+            _ => {
+                self.double_string.push_str(&ts.name.unwrap_or_default());
+                None
+            }
+        }
+    }
+
     fn eof_mode(&mut self) -> Option<TokenString> {
         if *self.indent_levels.last().unwrap() != 0 {
             self.indent_levels.pop();
@@ -487,10 +778,141 @@ impl<'input> FIRRTLLexer<'input> {
         }
     }
 
+    /// Keyword tokens that are spelled like identifiers, e.g. `wire` or `UInt`
+    fn is_keyword(ts: &TokenString) -> bool {
+        match (&ts.token, &ts.name) {
+            (Token::Identifier(_), _) => false,
+            (_, Some(name)) => {
+                let mut chars = name.chars();
+                chars.next().is_some_and(|c| c == '_' || c.is_ascii_alphabetic()) &&
+                    chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+            }
+            _ => false,
+        }
+    }
+
+    /// The next token that is not whitespace, lexing ahead if necessary
+    fn peek_token(&mut self) -> Option<&Token> {
+        self.peek_nth_token(0)
+    }
+
+    fn peek_nth_token(&mut self, n: usize) -> Option<&Token> {
+        loop {
+            if let Some(idx) = self.tokens.iter()
+                .enumerate()
+                .filter(|(_, ts)| !matches!(ts.token, Token::Space | Token::Tab | Token::Comment(_)))
+                .map(|(idx, _)| idx)
+                .nth(n) {
+                return Some(&self.tokens[idx].token);
+            }
+            let len = self.tokens.len();
+            self.try_push();
+            if self.tokens.len() == len {
+                return None;
+            }
+        }
+    }
+
+    fn in_field_position(&self) -> bool {
+        match &self.last_token {
+            Some(Token::LeftBracket) | Some(Token::LeftEnumBracket) => true,
+            Some(Token::Symbol(s)) if s == "," => self.bracket_num != 0 || !self.enum_brackets.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn in_name_position(&self) -> bool {
+        matches!(self.last_token,
+            Some(Token::Wire) | Some(Token::Reg) | Some(Token::RegReset) | Some(Token::Node) |
+            Some(Token::Inst) | Some(Token::InstChoice) | Some(Token::Of) | Some(Token::Mem) |
+            Some(Token::SMem) | Some(Token::CMem) | Some(Token::Mport) | Some(Token::Input) |
+            Some(Token::Output) | Some(Token::Circuit) | Some(Token::Module) | Some(Token::ExtModule) |
+            Some(Token::IntModule) | Some(Token::Class) | Some(Token::ExtClass) | Some(Token::Object) |
+            Some(Token::Layer) | Some(Token::LayerBlock) | Some(Token::EnableLayer) | Some(Token::Type) |
+            Some(Token::Option) | Some(Token::Parameter) | Some(Token::Flip))
+    }
+
+    fn in_reference_position(&self) -> bool {
+        match &self.last_token {
+            Some(Token::Connect) | Some(Token::Invalidate) | Some(Token::When) | Some(Token::Match) |
+            Some(Token::Define) | Some(Token::PropAssign) | Some(Token::LegacyConnect) |
+            Some(Token::LegacyPartialConnect) | Some(Token::LeftParenthesis) | Some(Token::LeftSquare) => true,
+            Some(Token::Symbol(s)) if s == "=" => {
+                // Not `type Foo = UInt<8>`
+                matches!(self.statement, Some(Token::Node) | Some(Token::Define) |
+                    Some(Token::Read) | Some(Token::Write) | Some(Token::Infer))
+            }
+            Some(Token::Symbol(s)) => s == ",",
+            _ => false,
+        }
+    }
+
+    /// Whether a keyword should be handed to the parser as an identifier instead.
+    /// Statement keywords only count at the start of a line and not before a connect, expression keywords only when
+    /// applied to arguments, and type keywords only where a type is expected.
+    fn keyword_is_identifier(&mut self, ts: &TokenString) -> bool {
+        if !Self::is_keyword(ts) {
+            return false;
+        }
+        if ts.token == Token::Flip && self.in_field_position() {
+            // `{flip a : UInt}` is a flipped field, `{flip : UInt}` one named `flip`
+            return self.peek_token() == Some(&Token::Symbol(":".to_string()));
+        }
+        if self.in_name_position() || self.in_field_position() {
+            return true;
+        }
+        if self.line_start {
+            // Only legacy statements start with a reference, e.g. `reader <= depth` or `reg.a is invalid`
+            return match self.peek_token() {
+                Some(Token::LegacyConnect) | Some(Token::LegacyPartialConnect) | Some(Token::Period) |
+                    Some(Token::LeftSquare) => true,
+                Some(Token::Is) => self.peek_nth_token(1) == Some(&Token::Invalid),
+                _ => false,
+            };
+        }
+        match &ts.token {
+            Token::Wire | Token::Reg | Token::RegReset | Token::Node | Token::Inst | Token::InstChoice |
+                Token::Mem | Token::SMem | Token::CMem | Token::Write | Token::Infer | Token::Depth |
+                Token::Reader | Token::Writer | Token::Readwriter | Token::Connect | Token::Invalidate |
+                Token::Attach | Token::Match | Token::Else | Token::Stop | Token::Printf | Token::Assert |
+                Token::Assume | Token::Cover | Token::Skip | Token::Force | Token::ForceInitial |
+                Token::Release | Token::ReleaseInitial | Token::Define | Token::PropAssign | Token::Object |
+                Token::Input | Token::Output | Token::ExtModule | Token::IntModule | Token::DefName |
+                Token::Parameter | Token::Class | Token::ExtClass | Token::FIRRTL | Token::Circuit |
+                Token::Public | Token::Type | Token::Option | Token::Layer | Token::LayerBlock => true,
+            Token::When => self.last_token != Some(Token::Else),
+            Token::Module => self.last_token != Some(Token::Public),
+            Token::Version => self.last_token != Some(Token::FIRRTL),
+            Token::Mport => !matches!(self.last_token, Some(Token::Read) | Some(Token::Write) | Some(Token::Infer)),
+            Token::Of | Token::EnableLayer | Token::Is | Token::Invalid | Token::With => self.in_reference_position(),
+            Token::Mux | Token::ValidIf | Token::E1Op(_) | Token::E2Op(_) | Token::E1I1Op(_) |
+                Token::PropPrimOp(_) | Token::ProbeType | Token::RWProbeType | Token::Read |
+                Token::Intrinsic | Token::Path => {
+                self.peek_token() != Some(&Token::LeftParenthesis)
+            }
+            Token::Clock | Token::Reset | Token::AsyncReset | Token::UInt | Token::SInt | Token::Probe |
+                Token::RWProbe | Token::Analog | Token::Fixed | Token::IntegerType | Token::StringType |
+                Token::BooleanType | Token::DoubleType | Token::PathType | Token::ListType |
+                Token::AnyRefType | Token::InstType | Token::Const | Token::Flip => {
+                self.in_reference_position() &&
+                    !matches!(self.peek_token(), Some(Token::LeftParenthesis) | Some(Token::LeftAngle))
+            }
+            _ => false,
+        }
+    }
+
     fn normal_mode(&mut self) -> Option<TokenString> {
-        let ts = self.tokens.pop_front().unwrap();
+        let mut ts = self.tokens.pop_front().unwrap();
+        if self.keyword_is_identifier(&ts) {
+            ts = TokenString::from((Token::Identifier(ts.name.unwrap()), ts.line, ts.start));
+        }
         match &ts.token {
+            Token::Comment(text) => {
+                self.retain_comment(text.clone(), ts.start);
+                None
+            }
             Token::Newline => {
+                self.line_start = true;
                 self.lineno += 1;
                 self.cur_indent = 0;
                 self.mode = LexerMode::Indent;
@@ -521,6 +943,12 @@ impl<'input> FIRRTLLexer<'input> {
                 self.angle_num -= 1;
                 Some(ts)
             }
+            Token::DoubleRight => {
+                // Closing a nested type such as `Probe<UInt<8>>`
+                self.angle_num -= 1;
+                self.tokens.push_front(TokenString::from((Token::RightAngle, ts.line, ts.start + 1)));
+                Some(TokenString::from((Token::RightAngle, ts.line, ts.start)))
+            }
             Token::LeftBracket => {
                 self.bracket_num += 1;
                 Some(ts)
@@ -529,8 +957,25 @@ impl<'input> FIRRTLLexer<'input> {
                 self.bracket_num -= 1;
                 Some(ts)
             }
+            Token::LeftEnumBracket => {
+                self.enum_brackets.push(self.bracket_num);
+                Some(ts)
+            }
+            Token::RightEnumBracket => {
+                self.enum_brackets.pop();
+                Some(ts)
+            }
+            Token::Symbol(s) if s == ":" && self.enum_brackets.last() == Some(&self.bracket_num) => {
+                // Separates a variant from its type. Without it, `{|A, B|}` and `{|A : B|}`
+                // where `B` is a type alias would be indistinguishable to the parser.
+                Some(TokenString::from((Token::EnumColon, ts.line, ts.start)))
+            }
             Token::LeftParenthesis => {
                 self.parenthesis_num += 1;
+                if self.last_token == Some(Token::DoubleType) {
+                    self.mode = LexerMode::Double;
+                    self.double_string = String::default();
+                }
                 Some(ts)
             }
             Token::RightParenthesis => {
@@ -556,6 +1001,17 @@ impl<'input> FIRRTLLexer<'input> {
                 self.mode = LexerMode::Anno;
                 None
             }
+            Token::Is |
+                Token::Invalid |
+                Token::With if self.syntax == SyntaxMode::Standard => {
+                // Only keywords in legacy FIRRTL
+                Some(TokenString::from((Token::Identifier(ts.name.unwrap()), ts.line, ts.start)))
+            }
+            Token::LegacyConnect |
+                Token::LegacyPartialConnect if self.syntax == SyntaxMode::Standard && !self.keep_legacy_connects => {
+                // Replaced by `connect` in FIRRTL 3.0.0
+                Some(TokenString::from((Token::Error, ts.line, ts.start)))
+            }
             _ => {
                 Some(ts)
             }
@@ -585,10 +1041,18 @@ impl<'input> FIRRTLLexer<'input> {
                 LexerMode::DotId  => { self.dotid_mode() }
                 LexerMode::Info   => { self.info_mode() }
                 LexerMode::Anno   => { self.anno_mode() }
+                LexerMode::Double => { self.double_mode() }
                 LexerMode::Normal => { self.normal_mode() }
             };
             match next_token_opt {
                 Some(ts) => {
+                    if !matches!(ts.token, Token::Indent | Token::Dedent) {
+                        if self.line_start {
+                            self.statement = Some(ts.token.clone());
+                        }
+                        self.last_token = Some(ts.token.clone());
+                        self.line_start = false;
+                    }
                     return Some(ts)
                 }
                 _ => {
@@ -1,24 +1,59 @@
 pub mod lexer;
+pub mod actions;
+pub mod info;
+pub mod inst_choice;
+pub mod string_literal;
+pub mod type_alias;
+pub mod version;
 
-use rusty_firrtl::Circuit;
+use rusty_firrtl::{Circuit, Version};
 use crate::firrtl::*;
 use crate::lexer::{FIRRTLLexer, Token, LexicalError};
+use crate::version::{declared_version, VersionCheck};
 use lalrpop_util::{lalrpop_mod, ParseError};
 
+pub use crate::lexer::{Comment, SyntaxMode};
+
 lalrpop_mod!(pub firrtl);
 
 pub type FIRRTLParserError = ParseError<usize, Token, LexicalError>;
 
-/// Given a path to a FIRRTL file, parse it and return a `Circuit` which represents the FIRRTL AST
+/// Given a path to a FIRRTL file, parse it and return a `Circuit` which represents the FIRRTL AST.
+/// Legacy FIRRTL syntax is accepted when the `FIRRTL version` header is older than 3.0.0 or missing.
 pub fn parse_circuit(source: &str) -> Result<Circuit, FIRRTLParserError> {
-    let lexer = FIRRTLLexer::new(source);
+    parse_circuit_with_mode(source, SyntaxMode::from_source(source))
+}
+
+/// Same as `parse_circuit`, but with an explicitly selected `SyntaxMode`
+pub fn parse_circuit_with_mode(source: &str, mode: SyntaxMode) -> Result<Circuit, FIRRTLParserError> {
+    let lexer = FIRRTLLexer::new_with_mode(source, mode);
     let parser = CircuitParser::new();
     parser.parse(lexer)
 }
 
+/// Same as `parse_circuit`, but rejects constructs that are not part of the FIRRTL version
+/// declared by the header, or 1.0.0 when there is none. `parse_circuit` skips this check, but still
+/// rejects `<=` and `<-` under a 3.0.0 or later header; `parse_circuit_with_mode` with
+/// `SyntaxMode::Legacy` accepts them regardless of the header.
+pub fn parse_circuit_checked(source: &str) -> Result<Circuit, FIRRTLParserError> {
+    let version = declared_version(source).unwrap_or(Version(1, 0, 0));
+    let lexer = FIRRTLLexer::new_with_mode(source, SyntaxMode::from_source(source));
+    let parser = CircuitParser::new();
+    parser.parse(VersionCheck::new(lexer, version))
+}
+
+/// Same as `parse_circuit`, but also returns the `;` comments of the source in order
+pub fn parse_circuit_with_comments(source: &str) -> Result<(Circuit, Vec<Comment>), FIRRTLParserError> {
+    let mut lexer = FIRRTLLexer::new_with_mode(source, SyntaxMode::from_source(source)).retain_comments();
+    let parser = CircuitParser::new();
+    let circuit = parser.parse(&mut lexer)?;
+    Ok((circuit, lexer.take_comments()))
+}
+
 #[cfg(test)]
 mod lexer_test {
     use crate::lexer::*;
+    use rusty_firrtl::Int;
 
     fn run(source: &str) {
         let mut lex = FIRRTLLexer::new(source);
@@ -98,12 +133,77 @@ circuit OneReadOneWritePortSRAM :
         run(source);
     }
 
+    #[test]
+    fn comments() {
+        let source =
+r#"; leading comment
+circuit Foo : ; trailing comment
+  ; indented comment
+  module Foo : @[Foo.scala 1:1] ; after info
+    input a : UInt<1> ;no space
+"#;
+        run(source);
+
+        let mut lex = FIRRTLLexer::new(source).retain_comments();
+        while let Some(ts) = lex.next_token() {
+            assert!(!matches!(ts.token, Token::Comment(_)));
+        }
+        let comments = lex.take_comments();
+        let lines: Vec<usize> = comments.iter().map(|c| c.line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 4, 5]);
+        assert_eq!(comments[1].text, " trailing comment");
+    }
+
+    #[test]
+    fn semicolons_outside_comments() {
+        let source =
+r#"circuit Foo :
+  module Foo : @[Foo;Bar.scala 1:1] ; comment
+    node `a;b` = c ; comment
+    node d = `a;b`
+"#;
+        run(source);
+
+        let mut lex = FIRRTLLexer::new(source).retain_comments();
+        let mut tokens = vec![];
+        while let Some(ts) = lex.next_token() {
+            tokens.push(ts.token);
+        }
+        assert!(tokens.contains(&Token::Info("Foo;Bar.scala 1:1".to_string())));
+        assert_eq!(tokens.iter().filter(|t| **t == Token::Identifier("a;b".to_string())).count(), 2);
+        assert_eq!(lex.take_comments().len(), 2);
+    }
+
     #[test]
     fn ports_2() {
         let source = r#"output io : { flip a : UInt<2>, flip b : UInt<2>, flip c : UInt<2>, flip sel : UInt<2>, output : UInt<2>}"#;
         run(source);
     }
 
+    #[test]
+    fn backtick_identifiers() {
+        let source = r#"connect `foo bar`.`0`, `0abc`[`12`] ; `not an id`"#;
+        let mut lex = FIRRTLLexer::new(source);
+        let mut tokens = vec![];
+        while let Some(ts) = lex.next_token() {
+            tokens.push(ts.token);
+        }
+        assert!(tokens.contains(&Token::Identifier("foo bar".to_string())));
+        assert!(tokens.contains(&Token::ID(Int::from_str("0").unwrap())));
+        assert!(tokens.contains(&Token::Identifier("0abc".to_string())));
+        assert!(tokens.contains(&Token::ID(Int::from_str("12").unwrap())));
+        assert!(!tokens.contains(&Token::Error));
+
+        let mut lex = FIRRTLLexer::new("node x = `007`\nnode y = `unterminated\nnode z = `ok`\n");
+        let mut tokens = vec![];
+        while let Some(ts) = lex.next_token() {
+            tokens.push(ts.token);
+        }
+        assert!(tokens.contains(&Token::Identifier("007".to_string())));
+        assert!(tokens.contains(&Token::Error));
+        assert!(tokens.contains(&Token::Identifier("ok".to_string())));
+    }
+
     #[test]
     fn extmodule() {
         let source =
@@ -194,6 +294,13 @@ circuit GCD :%[[
 mod parser_test {
     use crate::lexer::*;
     use crate::firrtl::*;
+    use crate::type_alias::*;
+    use crate::inst_choice::*;
+    use crate::info::*;
+    use crate::string_literal::*;
+    use crate::version::*;
+    use lalrpop_util::ParseError;
+    use rusty_firrtl::{Identifier, Int, Type, Version};
 
     #[test]
     fn stmts() {
@@ -418,6 +525,38 @@ circuit NestedWhen :
         println!("{:?}", ast);
     }
 
+    #[test]
+    fn else_when() {
+        let chained =
+r#"
+when _T : @[src/main/scala/gcd/NestedWhen.scala 17:28]
+  connect io.output, io.a @[src/main/scala/gcd/NestedWhen.scala 18:15]
+else when _T_1 : @[src/main/scala/gcd/NestedWhen.scala 19:35]
+  connect io.output, io.b @[src/main/scala/gcd/NestedWhen.scala 20:15]
+else when _T_2 :
+  connect io.output, io.c
+else :
+  connect io.output, io.d @[src/main/scala/gcd/NestedWhen.scala 22:15]
+"#;
+        let nested =
+r#"
+when _T : @[src/main/scala/gcd/NestedWhen.scala 17:28]
+  connect io.output, io.a @[src/main/scala/gcd/NestedWhen.scala 18:15]
+else :
+  when _T_1 : @[src/main/scala/gcd/NestedWhen.scala 19:35]
+    connect io.output, io.b @[src/main/scala/gcd/NestedWhen.scala 20:15]
+  else :
+    when _T_2 :
+      connect io.output, io.c
+    else :
+      connect io.output, io.d @[src/main/scala/gcd/NestedWhen.scala 22:15]
+"#;
+        let parser = StmtsParser::new();
+        let chained_ast = parser.parse(FIRRTLLexer::new(chained)).unwrap();
+        let nested_ast = parser.parse(FIRRTLLexer::new(nested)).unwrap();
+        assert_eq!(format!("{:?}", chained_ast), format!("{:?}", nested_ast));
+    }
+
     #[test]
     fn one_read_one_write_sram() {
         let source =
@@ -453,6 +592,148 @@ circuit OneReadOneWritePortSRAM :
         println!("{:?}", ast);
     }
 
+    #[test]
+    fn smem_read_under_write() {
+        let source =
+r#"
+smem old_mem : UInt<32> [1024], old @[src/main/scala/gcd/SRAM.scala 12:24]
+smem new_mem : UInt<32> [1024], new @[src/main/scala/gcd/SRAM.scala 13:24]
+smem undef_mem : UInt<2>[4] [8], undefined @[src/main/scala/gcd/SRAM.scala 14:24]
+smem plain_mem : UInt<2>[4] [8] @[src/main/scala/gcd/SRAM.scala 15:24]
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtsParser::new();
+        let ast = parser.parse(lexer).unwrap();
+
+        use rusty_firrtl::{Stmt, ChirrtlMemory, ChirrtlMemoryReadUnderWrite};
+        assert!(matches!(ast[0].as_ref(), Stmt::ChirrtlMemory(ChirrtlMemory::SMem(_, _, Some(ChirrtlMemoryReadUnderWrite::Old), _))));
+        assert!(matches!(ast[1].as_ref(), Stmt::ChirrtlMemory(ChirrtlMemory::SMem(_, _, Some(ChirrtlMemoryReadUnderWrite::New), _))));
+        assert!(matches!(ast[2].as_ref(), Stmt::ChirrtlMemory(ChirrtlMemory::SMem(_, _, Some(ChirrtlMemoryReadUnderWrite::Undefined), _))));
+        assert!(matches!(ast[3].as_ref(), Stmt::ChirrtlMemory(ChirrtlMemory::SMem(_, _, None, _))));
+    }
+
+    #[test]
+    fn smem_bad_read_under_write() {
+        let source = "smem mem : UInt<32> [1024], oldest @[src/main/scala/gcd/SRAM.scala 12:24]";
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtParser::new();
+        match parser.parse(lexer) {
+            Err(ParseError::User { error: LexicalError::InvalidReadUnderWrite(ruw) }) => assert_eq!(ruw, "oldest"),
+            x => panic!("Expected a read-under-write error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn mem() {
+        let source =
+r#"FIRRTL version 3.3.0
+circuit RegFile :
+  module RegFile : @[src/main/scala/gcd/RegFile.scala 5:7]
+    input clock : Clock
+    input raddr : UInt<5>
+    output rdata : UInt<64>
+    input waddr : UInt<5>
+    input wdata : UInt<64>
+
+    mem rf : @[src/main/scala/gcd/RegFile.scala 9:15]
+      data-type => UInt<64>
+      depth => 32
+      read-latency => 0
+      write-latency => 1
+      read-under-write => undefined
+      reader => r0
+      reader => r1
+      writer => w0
+      readwriter => rw0
+    connect rf.r0.addr, raddr
+    connect rf.r0.en, UInt<1>(0h1)
+    connect rf.r0.clk, clock
+    connect rdata, rf.r0.data
+    connect rf.w0.addr, waddr
+    connect rf.w0.data, wdata
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+    }
+
+    #[test]
+    fn mem_bad_read_under_write() {
+        let source =
+r#"mem rf :
+  data-type => UInt<8>
+  depth => 16
+  read-latency => 1
+  write-latency => 1
+  read-under-write => sometimes
+  reader => r
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtParser::new();
+        match parser.parse(lexer) {
+            Err(ParseError::User { error: LexicalError::InvalidReadUnderWrite(ruw) }) => assert_eq!(ruw, "sometimes"),
+            x => panic!("Expected a read-under-write error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn mem_scala_firrtl_order() {
+        // Field order of the Scala FIRRTL compiler, ports before read-under-write
+        let source =
+r#"mem m : @[Top.scala 4:7]
+  data-type => UInt<8>
+  depth => 16
+  read-latency => 0
+  write-latency => 1
+  reader => r
+  writer => w
+  read-under-write => undefined
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+        match ast {
+            rusty_firrtl::Stmt::Memory(m) => {
+                assert_eq!(m.ports.len(), 2);
+                assert!(matches!(m.ports[0].as_ref(), rusty_firrtl::MemoryPort::Read(_)));
+                assert!(matches!(m.ports[1].as_ref(), rusty_firrtl::MemoryPort::Write(_)));
+                assert!(matches!(m.read_under_write, rusty_firrtl::ChirrtlMemoryReadUnderWrite::Undefined));
+            }
+            x => panic!("Expected a mem, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn mem_missing_and_duplicate_fields() {
+        let missing =
+r#"mem m :
+  data-type => UInt<8>
+  read-latency => 0
+  write-latency => 1
+  read-under-write => old
+"#;
+        match StmtParser::new().parse(FIRRTLLexer::new(missing)) {
+            Err(ParseError::User { error: LexicalError::MissingMemoryField(field) }) => assert_eq!(field, "depth"),
+            x => panic!("Expected a missing field error, got {:?}", x),
+        }
+
+        let duplicate =
+r#"mem m :
+  depth => 16
+  data-type => UInt<8>
+  depth => 32
+  read-latency => 0
+  write-latency => 1
+  read-under-write => old
+"#;
+        match StmtParser::new().parse(FIRRTLLexer::new(duplicate)) {
+            Err(ParseError::User { error: LexicalError::DuplicateMemoryField(field) }) => assert_eq!(field, "depth"),
+            x => panic!("Expected a duplicate field error, got {:?}", x),
+        }
+    }
+
     #[test]
     fn as_clock_stmt() {
         let source = "node _childClock_T = asClock(UInt<1>(0h0)) @[generators/diplomacy/diplomacy/src/diplomacy/lazymodule/LazyModuleImp.scala 160:25]";
@@ -471,6 +752,145 @@ circuit OneReadOneWritePortSRAM :
         println!("{:?}", ast);
     }
 
+    #[test]
+    fn string_literals() {
+        let source = r#"printf(clock, en, "tab\there \"quoted\" back\\slash it\'s \u00e9\n", a) : p @[Top.scala 3:5]"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let msg = message(&ast).unwrap().unwrap();
+        assert_eq!(msg.raw, r#""tab\there \"quoted\" back\\slash it\'s \u00e9\n""#);
+        assert_eq!(msg.value, "tab\there \"quoted\" back\\slash it's \u{e9}\n");
+
+        let raw = parameter_value(&rusty_firrtl::Parameter::RawStringParam(
+                Identifier::Name("INIT".to_string()), "'a\\'b'".to_string())).unwrap().unwrap();
+        assert_eq!(raw.value, "a'b");
+        assert_eq!(decode_raw(r"'a\nb'"), Ok(r"a\nb".to_string()));
+        assert_eq!(decode_raw(r"'\foo '"), Ok(r"\foo ".to_string()));
+        assert_eq!(decode_raw(r#""a""#), Err(StringLiteralError::Unquoted(r#""a""#.to_string())));
+
+        assert_eq!(decode(r#""\q""#), Err(StringLiteralError::InvalidEscape('q')));
+        assert_eq!(decode(r#""\u12""#), Err(StringLiteralError::InvalidUnicode("\\u12".to_string())));
+        assert_eq!(decode("abc"), Err(StringLiteralError::Unquoted("abc".to_string())));
+        assert_eq!(decode("\""), Err(StringLiteralError::Unquoted("\"".to_string())));
+    }
+
+    #[test]
+    fn verification_stmts() {
+        let source =
+r#"
+assert(clock, _T, UInt<1>(0h1), "") : assert @[generators/rocket-chip/src/main/scala/tilelink/Monitor.scala 45:11]
+assert(clock, _T_1, _T_2, "fifo overflow") @[src/main/scala/Queue.scala 20:9]
+assert(clock, _T_3, en, "")
+assume(clock, in_valid_stable, UInt<1>(0h1), "input must stay valid") : assume @[src/main/scala/Formal.scala 12:9]
+assume(clock, _T_4, reset_done, "")
+cover(clock, full, UInt<1>(0h1), "queue full") : cover_full @[src/main/scala/Formal.scala 15:8]
+cover(clock, empty, UInt<1>(0h1), "") @[src/main/scala/Formal.scala 16:8]
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtsParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let stmts: Vec<(&str, Option<String>, String, String)> = ast.iter().map(|stmt| {
+            let (kind, name, msg, info) = match stmt.as_ref() {
+                rusty_firrtl::Stmt::Assert(name, _, _, _, msg, info) => ("assert", name, msg, info),
+                rusty_firrtl::Stmt::Assume(name, _, _, _, msg, info) => ("assume", name, msg, info),
+                rusty_firrtl::Stmt::Cover(name, _, _, _, msg, info)  => ("cover", name, msg, info),
+                x => panic!("expected a verification statement, got {:?}", x),
+            };
+            let name = name.as_ref().map(|n| match n {
+                Identifier::Name(n) => n.clone(),
+                _ => panic!("unexpected name {:?}", n),
+            });
+            (kind, name, msg.clone(), info.0.clone())
+        }).collect();
+        assert_eq!(stmts, vec![
+            ("assert", Some("assert".to_string()),     r#""""#.to_string(),                      "generators/rocket-chip/src/main/scala/tilelink/Monitor.scala 45:11".to_string()),
+            ("assert", None,                           r#""fifo overflow""#.to_string(),         "src/main/scala/Queue.scala 20:9".to_string()),
+            ("assert", None,                           r#""""#.to_string(),                      String::new()),
+            ("assume", Some("assume".to_string()),     r#""input must stay valid""#.to_string(), "src/main/scala/Formal.scala 12:9".to_string()),
+            ("assume", None,                           r#""""#.to_string(),                      String::new()),
+            ("cover",  Some("cover_full".to_string()), r#""queue full""#.to_string(),            "src/main/scala/Formal.scala 15:8".to_string()),
+            ("cover",  None,                           r#""""#.to_string(),                      "src/main/scala/Formal.scala 16:8".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn stop_stmt() {
+        let source =
+r#"
+stop(clock, _T_4, 1) : stop @[src/main/scala/chisel3/Stop.scala 12:9]
+stop(clock, _T_5, 0) @[src/main/scala/chisel3/Stop.scala 13:9]
+stop(clock, UInt<1>(0h1), 2) : stop_1
+stop(clock, done, 0)
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtsParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let stops: Vec<(Option<String>, Int, String)> = ast.iter().map(|stmt| match stmt.as_ref() {
+            rusty_firrtl::Stmt::Stop(name, _, _, code, info) => {
+                let name = name.as_ref().map(|n| match n {
+                    Identifier::Name(n) => n.clone(),
+                    _ => panic!("unexpected name {:?}", n),
+                });
+                (name, code.clone(), info.0.clone())
+            }
+            x => panic!("expected a stop, got {:?}", x),
+        }).collect();
+        assert_eq!(stops, vec![
+            (Some("stop".to_string()),   Int::from_str("1").unwrap(), "src/main/scala/chisel3/Stop.scala 12:9".to_string()),
+            (None,                       Int::from_str("0").unwrap(), "src/main/scala/chisel3/Stop.scala 13:9".to_string()),
+            (Some("stop_1".to_string()), Int::from_str("2").unwrap(), String::new()),
+            (None,                       Int::from_str("0").unwrap(), String::new()),
+        ]);
+    }
+
+    #[test]
+    fn literals_without_width() {
+        let source =
+r#"
+node a = UInt(5)
+node b = SInt(-3)
+node c = UInt(0h1f)
+node d = add(UInt<0h10>(0h3), UInt(1))
+wire e : UInt<0h10>
+wire f : SInt<0b101>
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtsParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        assert!(matches!(ast[4].as_ref(),
+            rusty_firrtl::Stmt::Wire(_, rusty_firrtl::Type::TypeGround(rusty_firrtl::TypeGround::UInt(Some(rusty_firrtl::Width(16)))), _)));
+        assert!(matches!(ast[5].as_ref(),
+            rusty_firrtl::Stmt::Wire(_, rusty_firrtl::Type::TypeGround(rusty_firrtl::TypeGround::SInt(Some(rusty_firrtl::Width(5)))), _)));
+    }
+
+    #[test]
+    fn invalid_radix_literal() {
+        // The lexer only produces well formed radix literals, so hand the parser a malformed one
+        let tokens = vec![
+            Token::UInt,
+            Token::LeftAngle,
+            Token::IntegerDec(Int::from_str("8").unwrap()),
+            Token::RightAngle,
+            Token::LeftParenthesis,
+            Token::RadixInt("0hXYZ".to_string()),
+            Token::RightParenthesis,
+        ];
+        let parser = ExprParser::new();
+        match parser.parse(tokens.into_iter().enumerate().map(|(i, t)| Ok((i, t, i + 1)))) {
+            Err(ParseError::User { error: LexicalError::InvalidRadixInteger(ri) }) => assert_eq!(ri, "0hXYZ"),
+            x => panic!("Expected a radix integer error, got {:?}", x),
+        }
+    }
+
     #[test]
     fn empty_type() {
         let source = "output auto : { } @[generators/diplomacy/diplomacy/src/diplomacy/lazymodule/LazyModuleImp.scala 107:25]";
@@ -496,6 +916,36 @@ r#"extmodule plusarg_reader : @[generators/rocket-chip/src/main/scala/util/PlusA
         println!("{:?}", ast);
     }
 
+    #[test]
+    fn extmodule_parameters() {
+        let source =
+r#"extmodule VendorRam : @[src/main/scala/VendorRam.scala 4:7]
+     input clock : Clock
+     defname = vendor_ram
+     parameter INIT = 'ram_init.hex'
+     parameter ESCAPED = "say \"hi\""
+     parameter OFFSET = -42
+     parameter SEED = 340282366920938463463374607431768211457
+     parameter MASK = 0hFFFF
+     parameter RATIO = 1.5"#;
+
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitModuleParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let params = match ast {
+            rusty_firrtl::CircuitModule::ExtModule(em) => em.params,
+            _ => panic!("expected an extmodule"),
+        };
+        assert!(matches!(params[0].as_ref(), rusty_firrtl::Parameter::RawStringParam(_, s) if s == "'ram_init.hex'"));
+        assert!(matches!(params[1].as_ref(), rusty_firrtl::Parameter::StringParam(_, s) if s == r#""say \"hi\"""#));
+        assert!(matches!(params[2].as_ref(), rusty_firrtl::Parameter::IntParam(_, i) if *i == Int::from_str("-42").unwrap()));
+        assert!(matches!(params[3].as_ref(), rusty_firrtl::Parameter::IntParam(_, i) if *i == Int::from_str("340282366920938463463374607431768211457").unwrap()));
+        assert!(matches!(params[4].as_ref(), rusty_firrtl::Parameter::IntParam(_, i) if *i == Int::from_str("65535").unwrap()));
+        assert!(matches!(params[5].as_ref(), rusty_firrtl::Parameter::FloatParam(..)));
+    }
+
     #[test] fn primop_name_overlaps_with_variable() {
         let source =
 r#"
@@ -584,6 +1034,1007 @@ extmodule GenericDigitalOutIOCell : @[generators/chipyard/src/main/scala/iocell/
         println!("{:?}", ast);
     }
 
+    #[test]
+    fn analog_attach() {
+        let source =
+r#"FIRRTL version 3.3.0
+circuit PadRing :
+  extmodule AnalogIOCell : @[generators/chipyard/src/main/scala/iocell/IOCell.scala 140:7]
+    output pad : Analog<1>
+    input ie : UInt<1>
+    defname = AnalogIOCell
+  module PadRing : @[generators/chipyard/src/main/scala/iocell/IOCell.scala 200:7]
+    input clock : Clock
+    output pll_ref : Analog<1>
+    output pad_bus : Analog<1> @[generators/chipyard/src/main/scala/iocell/IOCell.scala 201:14]
+    inst iocell of AnalogIOCell @[generators/chipyard/src/main/scala/iocell/IOCell.scala 210:24]
+    connect iocell.ie, UInt<1>(0h1)
+    attach(pll_ref, iocell.pad) @[generators/chipyard/src/main/scala/iocell/IOCell.scala 212:11]
+    wire bridge : Analog
+    attach(bridge, pad_bus, pll_ref)
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+    }
+
+    #[test]
+    fn probes() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Top :
+  module Child : @[src/main/scala/Probes.scala 8:7]
+    input clock : Clock
+    input in : UInt<8>
+    output p : Probe<UInt<8>> @[src/main/scala/Probes.scala 10:13]
+    output rw : RWProbe<{ a : UInt<1>, b : SInt<4>[2]}>
+
+    wire w : { a : UInt<1>, b : SInt<4>[2]}
+    invalidate w
+    define p = probe(in) @[src/main/scala/Probes.scala 12:9]
+    define rw = rwprobe(w)
+  module Top : @[src/main/scala/Probes.scala 20:7]
+    input clock : Clock
+    input cond : UInt<1>
+    input in : UInt<8>
+    output out : UInt<8>
+    output q : Probe<UInt<8>>
+
+    inst child of Child @[src/main/scala/Probes.scala 22:21]
+    connect child.clock, clock
+    connect child.in, in
+    define q = child.p
+    connect out, read(child.p) @[src/main/scala/Probes.scala 24:7]
+    force_initial(child.rw.a, UInt<1>(0h1))
+    release_initial(child.rw.a)
+    force(clock, cond, child.rw.b[0], SInt<4>(-2)) @[src/main/scala/Probes.scala 26:8]
+    release(clock, cond, child.rw.b[0])
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+    }
+
+    #[test]
+    fn intrinsics() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Top :
+  intmodule PlusArgsValue : @[src/main/scala/chisel3/util/circt/PlusArgsValue.scala 20:7]
+    output found : UInt<1>
+    output result : UInt<32>
+    intrinsic = circt_plusargs_value
+    parameter FORMAT = "foo=%d"
+  intmodule ClockGate :
+    input in : Clock
+    input en : UInt<1>
+    output out : Clock
+    intrinsic = circt_clock_gate
+  module Top : @[src/main/scala/Top.scala 5:7]
+    input clock : Clock
+    input en : UInt<1>
+    output gated : Clock
+    output value : UInt<32>
+
+    node plusarg = intrinsic(circt_plusargs_value<FORMAT = "foo=%d"> : { found : UInt<1>, result : UInt<32>}) @[src/main/scala/Top.scala 8:32]
+    connect value, plusarg.result
+    node gate = intrinsic(circt_clock_gate : Clock, clock, en)
+    connect gated, gate
+    node size = intrinsic(circt_sizeof : UInt<32>, value)
+    intrinsic(circt_chisel_assert<format = "value was %d", label = "check">, clock, en, en, value) @[src/main/scala/Top.scala 12:9]
+    intrinsic(circt_init_forbidden)
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+
+        let stmts = match ast.modules[2].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => &m.stmts,
+            _ => panic!("expected a module"),
+        };
+        let node = |name: &str| stmts.iter().find_map(|stmt| match stmt.as_ref() {
+            rusty_firrtl::Stmt::Node(Identifier::Name(n), expr, _) if n == name => Some(expr.clone()),
+            _ => None,
+        }).unwrap();
+
+        match node("gate") {
+            rusty_firrtl::Expr::Intrinsic(Identifier::Name(name), params, tpe, args) => {
+                assert_eq!(name, "circt_clock_gate");
+                assert!(params.is_empty());
+                assert_eq!(tpe, Type::TypeGround(rusty_firrtl::TypeGround::Clock));
+                assert_eq!(args.len(), 2);
+            }
+            other => panic!("expected an intrinsic expression, got {:?}", other),
+        }
+        match node("plusarg") {
+            rusty_firrtl::Expr::Intrinsic(Identifier::Name(name), params, _, args) => {
+                assert_eq!(name, "circt_plusargs_value");
+                assert!(matches!(params[0].as_ref(),
+                    rusty_firrtl::Parameter::StringParam(Identifier::Name(p), v) if p == "FORMAT" && v == "\"foo=%d\""));
+                assert!(args.is_empty());
+            }
+            other => panic!("expected an intrinsic expression, got {:?}", other),
+        }
+
+        let last: Vec<&rusty_firrtl::Stmt> = stmts.iter().rev().take(2).map(|stmt| stmt.as_ref()).collect();
+        match last[1] {
+            rusty_firrtl::Stmt::Intrinsic(Identifier::Name(name), params, args, _) => {
+                assert_eq!(name, "circt_chisel_assert");
+                assert_eq!(params.len(), 2);
+                assert_eq!(args.len(), 4);
+            }
+            other => panic!("expected an intrinsic statement, got {:?}", other),
+        }
+        assert!(matches!(last[0],
+            rusty_firrtl::Stmt::Intrinsic(Identifier::Name(name), params, args, _)
+                if name == "circt_init_forbidden" && params.is_empty() && args.is_empty()));
+    }
+
+    #[test]
+    fn const_types() {
+        let source =
+r#"
+input clock : Clock
+input width : const UInt<8> @[src/main/scala/Consts.scala 7:17]
+input table : const SInt<4>[4]
+input cfg : const { mode : UInt<2>, flip ack : UInt<1>, lanes : UInt<3>[2]}
+input mixed : { a : const UInt<1>, b : UInt<1>}
+output out : { valid : UInt<1>, bits : const UInt<8>}[2]
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = PortsParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let types: Vec<&Type> = ast.iter().map(|p| match p.as_ref() {
+            rusty_firrtl::Port::Input(_, tpe, _) | rusty_firrtl::Port::Output(_, tpe, _) => tpe,
+        }).collect();
+        let fields = |tpe: &Type| -> Vec<Type> {
+            match tpe {
+                Type::TypeAggregate(ta) => match ta.as_ref() {
+                    rusty_firrtl::TypeAggregate::Fields(fields) => fields.iter().map(|f| match f.as_ref() {
+                        rusty_firrtl::Field::Straight(_, t) | rusty_firrtl::Field::Flipped(_, t) => t.as_ref().clone(),
+                    }).collect(),
+                    rusty_firrtl::TypeAggregate::Array(elem, _) => vec![elem.as_ref().clone()],
+                    _ => panic!("unexpected aggregate {:?}", ta),
+                },
+                _ => panic!("expected a non-const aggregate, got {:?}", tpe),
+            }
+        };
+
+        assert!(matches!(types[0], Type::TypeGround(_)));
+        assert!(matches!(types[1], Type::ConstTypeGround(_)));
+        assert!(matches!(types[2], Type::ConstTypeAggregate(ta) if matches!(ta.as_ref(), rusty_firrtl::TypeAggregate::Array(..))));
+        assert!(matches!(types[3], Type::ConstTypeAggregate(ta) if matches!(ta.as_ref(), rusty_firrtl::TypeAggregate::Fields(..))));
+
+        let mixed = fields(types[4]);
+        assert!(matches!(mixed[0], Type::ConstTypeGround(_)));
+        assert!(matches!(mixed[1], Type::TypeGround(_)));
+
+        let out = fields(&fields(types[5])[0]);
+        assert!(matches!(out[0], Type::TypeGround(_)));
+        assert!(matches!(out[1], Type::ConstTypeGround(_)));
+    }
+
+    #[test]
+    fn layers() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Top :
+  layer Verification, bind : @[src/main/scala/Layers.scala 5:9]
+    layer Assert, bind, "verification/assert" :
+    layer Debug, bind :
+      layer Trace, inline :
+  layer Perf, inline :
+  module Top enablelayer Verification enablelayer Verification.Assert : @[src/main/scala/Layers.scala 10:7]
+    input clock : Clock
+    input a : UInt<8>
+    output b : UInt<8>
+
+    connect b, a
+    layerblock Verification : @[src/main/scala/Layers.scala 14:20]
+      node a_is_zero = eq(a, UInt<8>(0h0))
+      layerblock Assert :
+        assert(clock, a_is_zero, UInt<1>(0h1), "a must be zero") : a_zero
+      layerblock Debug :
+        layerblock Trace :
+          printf(clock, UInt<1>(0h1), "a: %d\n", a)
+    layerblock Perf :
+      skip
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+
+        // `Name(Convention)[children]` for the layer tree and `Name[children]` for layer blocks
+        fn layer_tree(layer: &rusty_firrtl::Layer) -> String {
+            let children: Vec<String> = layer.layers.iter().map(|l| layer_tree(l)).collect();
+            format!("{:?}({:?})[{}]", layer.name, layer.convention, children.join(","))
+        }
+        fn block_tree(stmts: &rusty_firrtl::Stmts) -> Vec<String> {
+            stmts.iter().filter_map(|stmt| match stmt.as_ref() {
+                rusty_firrtl::Stmt::LayerBlock(name, stmts, _) => Some(format!("{:?}[{}]", name, block_tree(stmts).join(","))),
+                _ => None,
+            }).collect()
+        }
+        let name = |n: &str| format!("{:?}", Identifier::Name(n.to_string()));
+
+        let layers: Vec<&rusty_firrtl::Layer> = ast.modules.iter().filter_map(|m| match m.as_ref() {
+            rusty_firrtl::CircuitModule::Layer(l) => Some(l),
+            _ => None,
+        }).collect();
+        let trees: Vec<String> = layers.iter().map(|l| layer_tree(l)).collect();
+        assert_eq!(trees, vec![
+            format!("{}(Bind)[{}(Bind)[],{}(Bind)[{}(Inline)[]]]", name("Verification"), name("Assert"), name("Debug"), name("Trace")),
+            format!("{}(Inline)[]", name("Perf")),
+        ]);
+        assert_eq!(layers[0].layers[0].output_dir, Some("\"verification/assert\"".to_string()));
+        assert_eq!(layers[0].output_dir, None);
+
+        let module = ast.modules.iter().find_map(|m| match m.as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => Some(m),
+            _ => None,
+        }).unwrap();
+        assert_eq!(block_tree(&module.stmts), vec![
+            format!("{}[{}[],{}[{}[]]]", name("Verification"), name("Assert"), name("Debug"), name("Trace")),
+            format!("{}[]", name("Perf")),
+        ]);
+        let assert_block = module.stmts.iter().find_map(|stmt| match stmt.as_ref() {
+            rusty_firrtl::Stmt::LayerBlock(_, stmts, _) => stmts.iter().find_map(|stmt| match stmt.as_ref() {
+                rusty_firrtl::Stmt::LayerBlock(_, stmts, _) => Some(stmts),
+                _ => None,
+            }),
+            _ => None,
+        }).unwrap();
+        assert!(matches!(assert_block[0].as_ref(), rusty_firrtl::Stmt::Assert(..)));
+    }
+
+    #[test]
+    fn layer_colored_probes() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Top :
+  layer Verification, bind :
+    layer Assert, bind :
+  module Top : @[src/main/scala/Top.scala 5:7]
+    input in : UInt<1>
+    output p : Probe<UInt<1>, Verification>
+    output rw : RWProbe<UInt<1>, Verification.Assert> @[src/main/scala/Top.scala 7:14]
+
+    layerblock Verification :
+      define p = probe(in)
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let module = match ast.modules[1].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => m,
+            _ => panic!("expected a module"),
+        };
+        match module.ports[1].as_ref() {
+            rusty_firrtl::Port::Output(_, Type::TypeProbe(rusty_firrtl::TypeProbe::Probe(_, Some(layer))), _) => {
+                assert!(matches!(layer, rusty_firrtl::Reference::Ref(Identifier::Name(name)) if name == "Verification"));
+            }
+            x => panic!("expected a layer-colored probe, got {:?}", x),
+        }
+        match module.ports[2].as_ref() {
+            rusty_firrtl::Port::Output(_, Type::TypeProbe(rusty_firrtl::TypeProbe::RWProbe(_, Some(layer))), _) => {
+                assert!(matches!(layer, rusty_firrtl::Reference::RefDot(_, Identifier::Name(name)) if name == "Assert"));
+            }
+            x => panic!("expected a layer-colored rwprobe, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn enums() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Fsm :
+  module Fsm : @[src/main/scala/Fsm.scala 8:7]
+    input clock : Clock
+    input reset : UInt<1>
+    input in : {|Idle, Busy : UInt<4>, Done : { code : UInt<2>, ok : UInt<1>}|}
+    output out : UInt<4>
+    output states : {||}[2]
+
+    wire next : {|Idle, Busy : UInt<4>, Done : { code : UInt<2>, ok : UInt<1>}|} @[src/main/scala/Fsm.scala 12:18]
+    connect next, {|Idle, Busy : UInt<4>, Done : { code : UInt<2>, ok : UInt<1>}|}(Idle)
+    connect out, UInt<4>(0h0)
+    match in : @[src/main/scala/Fsm.scala 14:14]
+      Idle :
+        connect next, {|Idle, Busy : UInt<4>, Done : { code : UInt<2>, ok : UInt<1>}|}(Busy, UInt<4>(0h1))
+      Busy(cnt) :
+        connect out, cnt
+        when eq(cnt, UInt<4>(0hf)) :
+          skip
+      Done(d) :
+        skip
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+
+        let stmts = match ast.modules[0].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => &m.stmts,
+            _ => panic!("expected a module"),
+        };
+        let arms = stmts.iter().find_map(|stmt| match stmt.as_ref() {
+            rusty_firrtl::Stmt::Match(_, _, arms) => Some(arms),
+            _ => None,
+        }).unwrap();
+        let variants: Vec<(Identifier, Option<Identifier>)> = arms.iter()
+            .map(|arm| (arm.variant.clone(), arm.binding.clone()))
+            .collect();
+        let name = |n: &str| Identifier::Name(n.to_string());
+        assert_eq!(variants, vec![
+            (name("Idle"), None),
+            (name("Busy"), Some(name("cnt"))),
+            (name("Done"), Some(name("d"))),
+        ]);
+
+        match arms[0].stmts[0].as_ref() {
+            rusty_firrtl::Stmt::Connect(_, rusty_firrtl::Expr::Enum(_, variant, Some(payload)), _) => {
+                assert_eq!(*variant, name("Busy"));
+                assert!(matches!(payload.as_ref(),
+                    rusty_firrtl::Expr::UIntInit(_, value) if *value == Int::from_str("1").unwrap()));
+            }
+            other => panic!("expected a connect of an enum value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn properties() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Top :
+  extclass ExtInfo :
+    input name : String
+  class SRAMInfo : @[src/main/scala/OM.scala 12:7]
+    input depth : Integer
+    input width : Integer
+    output bits : Integer
+    output name : String
+    output tags : List<String>
+    output ref : Path
+    output enabled : Boolean
+    output freq : Double
+    output parent : AnyRef
+
+    propassign bits, integer_mul(depth, width) @[src/main/scala/OM.scala 15:10]
+    propassign name, String("sram")
+    propassign tags, list_concat(List<String>(String("a"), String("b")), List<String>())
+    propassign ref, path("OMReferenceTarget:~Top|Top>mem")
+    propassign enabled, Boolean(true)
+    propassign freq, Double(1.5)
+  class Empty :
+    output x : Integer
+  module Top : @[src/main/scala/Top.scala 5:7]
+    input clock : Clock
+    output info : Inst<SRAMInfo>
+    output size : Integer
+
+    object sram_info of SRAMInfo @[src/main/scala/Top.scala 9:22]
+    propassign sram_info.depth, Integer(1024)
+    propassign sram_info.width, Integer(-32)
+    propassign info, sram_info
+    propassign size, integer_shl(sram_info.bits, Integer(3))
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+    }
+
+    #[test]
+    fn double_literals() {
+        let source =
+r#"
+propassign a, Double(1.5)
+propassign b, Double(-3)
+propassign c, Double(-0.25)
+propassign d, Double(1.05)
+propassign e, Double(2.5e3)
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtsParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let values: Vec<f64> = ast.iter().map(|stmt| match stmt.as_ref() {
+            rusty_firrtl::Stmt::PropAssign(_, rusty_firrtl::PropExpr::Double(d), _) => *d,
+            x => panic!("expected a Double propassign, got {:?}", x),
+        }).collect();
+        assert_eq!(values, vec![1.5, -3.0, -0.25, 1.05, 2500.0]);
+
+        let lexer = FIRRTLLexer::new("propassign a, Double(1.2.3)");
+        match StmtParser::new().parse(lexer) {
+            Err(ParseError::User { error: LexicalError::InvalidDouble(d) }) => assert_eq!(d, "1.2.3"),
+            x => panic!("Expected a double error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn type_aliases() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Top :
+  type Byte = UInt<8>
+  type Pair = { a : Byte, flip b : SInt<4>} @[src/main/scala/Types.scala 3:8]
+  type Pairs = Pair[2]
+  type Cmd = {|Nop, Load : Byte|}
+  type Loop = { next : Loop}
+  module Top : @[src/main/scala/Top.scala 6:7]
+    input clock : Clock
+    input in : Pairs
+    input cmd : Cmd
+    output out : Byte
+
+    wire w : Pair
+    wire c : {|Nop, Load : Byte|}
+    wire d : {|Nop, Byte|}
+    reg r : Byte[4], clock
+    connect out, in[0].a
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let aliases = TypeAliases::from_circuit(&ast);
+        let pairs = Type::TypeAlias(Identifier::Name("Pairs".to_string()));
+        let resolved = aliases.resolve(&pairs).unwrap();
+        println!("{:?}", resolved);
+        assert!(!format!("{:?}", resolved).contains("TypeAlias"));
+
+        let cmd = aliases.resolve(&Type::TypeAlias(Identifier::Name("Cmd".to_string()))).unwrap();
+        assert!(!format!("{:?}", cmd).contains("TypeAlias"));
+
+        let missing = Identifier::Name("Missing".to_string());
+        assert!(matches!(aliases.resolve(&Type::TypeAlias(missing.clone())), Err(TypeAliasError::Undefined(name)) if name == missing));
+
+        let looped = Identifier::Name("Loop".to_string());
+        assert!(matches!(aliases.resolve(&Type::TypeAlias(looped.clone())), Err(TypeAliasError::Cyclic(name)) if name == looped));
+    }
+
+    #[test]
+    fn inst_choice() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Top :
+  option Platform : @[src/main/scala/Top.scala 3:8]
+    FPGA
+    ASIC @[src/main/scala/Top.scala 5:5]
+  module DefaultTarget : @[src/main/scala/Top.scala 7:7]
+    input clock : Clock
+    skip
+  module FPGATarget : @[src/main/scala/Top.scala 8:7]
+    input clock : Clock
+    skip
+  module ASICTarget : @[src/main/scala/Top.scala 9:7]
+    input clock : Clock
+    skip
+  module Top : @[src/main/scala/Top.scala 11:7]
+    input clock : Clock
+    input en : UInt<1>
+
+    instchoice target of DefaultTarget, Platform : @[src/main/scala/Top.scala 14:20]
+      FPGA => FPGATarget
+      ASIC => ASICTarget
+    connect target.clock, clock
+    when en :
+      instchoice fpga_only of DefaultTarget, Platform : FPGA => FPGATarget
+      connect fpga_only.clock, clock
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let platform = Identifier::Name("Platform".to_string());
+        let asic = select_case(&ast, &platform, &Identifier::Name("ASIC".to_string())).unwrap();
+        println!("{:?}", asic);
+        let asic = format!("{:?}", asic);
+        assert!(!asic.contains("InstChoice") && !asic.contains("OptionGroup"));
+        assert!(asic.contains(r#"Inst(Name("target"), Name("ASICTarget")"#));
+        assert!(asic.contains(r#"Inst(Name("fpga_only"), Name("DefaultTarget")"#));
+
+        let gpu = Identifier::Name("GPU".to_string());
+        assert!(matches!(select_case(&ast, &platform, &gpu), Err(InstChoiceError::UndefinedCase(_, name)) if name == gpu));
+        assert!(matches!(select_case(&ast, &gpu, &gpu), Err(InstChoiceError::UndefinedOption(name)) if name == gpu));
+    }
+
+    #[test]
+    fn public_modules() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Harness :
+  layer Verification, bind :
+  public module DUT enablelayer Verification : @[src/main/scala/DUT.scala 5:7]
+    input clock : Clock
+    skip
+  module Queue : @[src/main/scala/Queue.scala 5:7]
+    input clock : Clock
+    skip
+  public module Harness : @[src/main/scala/Harness.scala 5:7]
+    input clock : Clock
+    inst dut of DUT
+    connect dut.clock, clock
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let modules: Vec<&rusty_firrtl::Module> = ast.modules.iter().filter_map(|m| match m.as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => Some(m),
+            _ => None,
+        }).collect();
+        let public: Vec<bool> = modules.iter().map(|m| m.public).collect();
+        assert_eq!(public, vec![true, false, true]);
+        assert_eq!(modules[0].enable_layers.len(), 1);
+        assert!(modules[1].enable_layers.is_empty());
+    }
+
+    #[test]
+    fn empty_modules() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit Top :
+  module Empty :
+  module NoPorts : @[src/main/scala/NoPorts.scala 1:7]
+    skip
+  module NoStmts : @[src/main/scala/NoStmts.scala 1:7]
+    input clock : Clock
+  extmodule BlackBox : @[src/main/scala/BlackBox.scala 1:7]
+    input clock : Clock
+  extmodule Params : @[src/main/scala/Params.scala 1:7]
+    parameter WIDTH = 8
+  extmodule Bare :
+  module Top : @[src/main/scala/Top.scala 1:7]
+    input clock : Clock
+    inst e of Empty
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+        assert_eq!(ast.modules.len(), 7);
+
+        match ast.modules[0].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => {
+                assert!(m.ports.is_empty() && m.stmts.is_empty());
+                assert_eq!(m.info, rusty_firrtl::Info::default());
+            }
+            _ => panic!("expected a module"),
+        }
+        match ast.modules[1].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => assert!(m.ports.is_empty() && m.stmts.len() == 1),
+            _ => panic!("expected a module"),
+        }
+        match ast.modules[2].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => assert!(m.ports.len() == 1 && m.stmts.is_empty()),
+            _ => panic!("expected a module"),
+        }
+        match ast.modules[3].as_ref() {
+            rusty_firrtl::CircuitModule::ExtModule(em) => assert_eq!(em.defname.0, em.name),
+            _ => panic!("expected an extmodule"),
+        }
+        match ast.modules[4].as_ref() {
+            rusty_firrtl::CircuitModule::ExtModule(em) => assert!(em.ports.is_empty() && em.params.len() == 1),
+            _ => panic!("expected an extmodule"),
+        }
+        match ast.modules[5].as_ref() {
+            rusty_firrtl::CircuitModule::ExtModule(em) => {
+                assert!(em.ports.is_empty() && em.params.is_empty());
+                assert_eq!(em.defname.0, em.name);
+                assert_eq!(em.info, rusty_firrtl::Info::default());
+            }
+            _ => panic!("expected an extmodule"),
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let source =
+r#"; Hand-written fixture
+FIRRTL version 3.3.0
+circuit Adder : ; the top
+  ; a comment at module indentation
+  module Adder : @[Adder.scala 3:7]
+    input a : UInt<8> ; operand
+    input b : UInt<8>
+        ; deeper than the surrounding statements
+    output c : UInt<9>
+
+; between statements
+    connect c, add(a, b) ; "quoted ; text"
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+
+        let (_, comments) = crate::parse_circuit_with_comments(source).unwrap();
+        let found: Vec<(usize, &str)> = comments.iter().map(|c| (c.line, c.text.as_str())).collect();
+        assert_eq!(found, vec![
+            (1, " Hand-written fixture"),
+            (3, " the top"),
+            (4, " a comment at module indentation"),
+            (6, " operand"),
+            (8, " deeper than the surrounding statements"),
+            (11, " between statements"),
+            (12, r#" "quoted ; text""#),
+        ]);
+    }
+
+    #[test]
+    fn source_locators() {
+        let source =
+r#"node _T = add(a, b) @[src/main/scala/a.scala 12:3 src/main/scala/b.scala 40:{5,9}]
+node _T_1 = eq(x, y) @[src/main/scala/c.scala {7:2,9:{1,14}}]
+node _T_2 = not(x) @[generators/rocket-chip/src/main/scala/util/PlusArg.scala 45]
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = StmtsParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        let infos: Vec<rusty_firrtl::Info> = ast.iter().map(|stmt| match stmt.as_ref() {
+            rusty_firrtl::Stmt::Node(_, _, info) => info.clone(),
+            _ => unreachable!(),
+        }).collect();
+
+        let loc = |file: &str, line: u32, columns: Vec<u32>| SourceLocator { file: file.to_string(), line, columns };
+        assert_eq!(parse_info(&infos[0]).unwrap(), vec![
+            loc("src/main/scala/a.scala", 12, vec![3]),
+            loc("src/main/scala/b.scala", 40, vec![5, 9]),
+        ]);
+        assert_eq!(parse_info(&infos[1]).unwrap(), vec![
+            loc("src/main/scala/c.scala", 7, vec![2]),
+            loc("src/main/scala/c.scala", 9, vec![1, 14]),
+        ]);
+        assert_eq!(parse_info(&infos[2]).unwrap(), vec![
+            loc("generators/rocket-chip/src/main/scala/util/PlusArg.scala", 45, vec![]),
+        ]);
+
+        assert_eq!(parse_locators("a.scala"), Err(InfoError::MissingPosition("a.scala".to_string())));
+        assert_eq!(parse_locators("a.scala 3:{4,"), Err(InfoError::InvalidPosition("3:{4,".to_string())));
+        assert_eq!(parse_locators("a.scala 3:4x"), Err(InfoError::InvalidPosition("3:4x".to_string())));
+    }
+
+    #[test]
+    fn backtick_identifiers() {
+        let source =
+r#"FIRRTL version 4.0.0
+circuit `Top-Level` :
+  module `Top-Level` : @[src/main/scala/Top.scala 3:7]
+    input clock : Clock
+    output io : { `0` : UInt<1>, `1` : UInt<1>, `a b` : UInt<8>, `0abc` : UInt<8>}
+
+    wire `foo bar` : UInt<8>
+    wire `wire` : UInt<8>
+    connect `foo bar`, io.`a b` @[src/main/scala/Top.scala 8:11]
+    connect `wire`, io.`0abc`
+    connect io.`0`, UInt<1>(0h0)
+    connect io.`1`, bits(`foo bar`, 0, 0)
+    inst `u$0` of `Top-Level`
+"#;
+        let lexer = FIRRTLLexer::new(source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+        println!("{:?}", ast);
+        assert!(matches!(&ast.name, rusty_firrtl::Identifier::Name(name) if name == "Top-Level"));
+    }
+
+    #[test]
+    fn keywords_as_identifiers() {
+        let keywords = [
+            "Clock", "Reset", "AsyncReset", "UInt", "SInt", "probe", "Probe", "rwprobe", "RWProbe",
+            "Analog", "Fixed", "flip", "Integer", "String", "Boolean", "Double", "Path", "List",
+            "AnyRef", "Inst", "path", "integer_add", "integer_mul", "integer_shr", "integer_shl",
+            "list_concat", "add", "sub", "mul", "div", "rem", "lt", "leq", "gt", "geq", "eq", "neq",
+            "dshl", "dshr", "and", "or", "xor", "cat", "asUInt", "asSInt", "asClock", "asAsyncReset",
+            "cvt", "neg", "not", "andr", "orr", "xorr", "pad", "shl", "shr", "head", "tail", "mux",
+            "validif", "mem", "smem", "cmem", "write", "read", "infer", "mport", "depth", "reader",
+            "writer", "readwriter", "wire", "reg", "regreset", "inst", "of", "instchoice", "option",
+            "node", "invalidate", "attach", "when", "match", "else", "stop", "printf", "assert",
+            "assume", "cover", "skip", "force", "force_initial", "release", "release_initial",
+            "input", "output", "module", "extmodule", "defname", "parameter", "intmodule",
+            "intrinsic", "class", "extclass", "object", "propassign", "FIRRTL", "version", "circuit",
+            "connect", "is", "invalid", "with", "public", "define", "const", "type", "layer",
+            "layerblock", "enablelayer",
+        ];
+
+        let fields: Vec<String> = keywords.iter().map(|kw| format!("{} : UInt<1>", kw)).collect();
+        let mut source = format!(
+"FIRRTL version 4.0.0
+circuit Top :
+  module Top : @[Top.scala 1:1]
+    output io : {{ {} }}
+", fields.join(", "));
+        for kw in keywords.iter() {
+            source.push_str(&format!("    wire {} : UInt<1>\n", kw));
+            source.push_str(&format!("    connect io.{}, {} @[Top.scala 2:3]\n", kw, kw));
+        }
+
+        let lexer = FIRRTLLexer::new(&source);
+        let parser = CircuitParser::new();
+        let ast = parser.parse(lexer).unwrap();
+
+        let module = match ast.modules[0].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => m,
+            _ => panic!("expected a module"),
+        };
+        let field_names: Vec<String> = match module.ports[0].as_ref() {
+            rusty_firrtl::Port::Output(_, Type::TypeAggregate(ta), _) => match ta.as_ref() {
+                rusty_firrtl::TypeAggregate::Fields(fields) => fields.iter().map(|f| match f.as_ref() {
+                    rusty_firrtl::Field::Straight(Identifier::Name(name), _) => name.clone(),
+                    _ => panic!("expected a named, unflipped field"),
+                }).collect(),
+                _ => panic!("expected a bundle"),
+            },
+            _ => panic!("expected a bundle output"),
+        };
+        let wire_names: Vec<String> = module.stmts.iter().filter_map(|stmt| match stmt.as_ref() {
+            rusty_firrtl::Stmt::Wire(Identifier::Name(name), _, _) => Some(name.clone()),
+            _ => None,
+        }).collect();
+
+        assert_eq!(field_names, keywords);
+        assert_eq!(wire_names, keywords);
+        assert_eq!(module.stmts.len(), 2 * keywords.len());
+    }
+
+    #[test]
+    fn legacy_syntax() {
+        let source =
+r#"circuit Counter :
+  module Counter : @[Counter.scala 5:7]
+    input clock : Clock
+    input reset : UInt<1>
+    input en : UInt<1>
+    output io : { flip in : UInt<8>, out : UInt<8>}
+
+    reg count : UInt<8>, clock with : (reset => (reset, UInt<8>("h0"))) @[Counter.scala 8:22]
+    reg last : UInt<8>, clock with :
+      reset => (UInt<1>("h0"), last) @[Counter.scala 9:21]
+    wire tmp : UInt<8>
+    tmp is invalid @[Counter.scala 10:7]
+    node _T = add(count, UInt(1)) @[Counter.scala 11:20]
+    node _T_1 = tail(_T, 1)
+    when en : @[Counter.scala 11:14]
+      count <= _T_1 @[Counter.scala 11:20]
+    io.out <= count
+    tmp <- io.in
+    last <= SInt<8>("h-3")
+    mem ram :
+      data-type => UInt<8>
+      depth => 16
+      read-latency => 1
+      write-latency => 1
+      read-under-write => undefined
+      reader => r
+"#;
+        assert_eq!(SyntaxMode::from_source(source), SyntaxMode::Legacy);
+        let ast = crate::parse_circuit(source).expect("FAILED");
+        println!("{:?}", ast);
+
+        let versioned = format!("FIRRTL version 2.0.0\n{}", source);
+        assert_eq!(SyntaxMode::from_source(&versioned), SyntaxMode::Legacy);
+        crate::parse_circuit(&versioned).expect("FAILED");
+
+        assert!(crate::parse_circuit_with_mode(source, SyntaxMode::Standard).is_err());
+
+        let stmts = match ast.modules[0].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => &m.stmts,
+            _ => panic!("expected a module"),
+        };
+        let partial = stmts.iter().filter(|s| matches!(s.as_ref(), rusty_firrtl::Stmt::PartialConnect(..))).count();
+        assert_eq!(partial, 1);
+
+        for connect in ["b <= a", "b <- a"] {
+            let standard = format!("FIRRTL version 4.0.0\ncircuit Top :\n  module Top : @[Top.scala 1:7]\n    input a : UInt<1>\n    output b : UInt<1>\n    {}\n", connect);
+            assert!(crate::parse_circuit(&standard).is_err(), "{} should be rejected", connect);
+            crate::parse_circuit_with_mode(&standard, SyntaxMode::Legacy).expect("FAILED");
+        }
+    }
+
+    #[test]
+    fn keywords_at_statement_start() {
+        let keywords = [
+            "wire", "reg", "node", "inst", "mem", "smem", "cmem", "read", "write", "infer", "mport",
+            "reader", "writer", "readwriter", "depth", "when", "else", "stop", "printf", "assert",
+            "assume", "cover", "skip", "attach", "connect", "invalidate", "define", "propassign",
+            "force", "release", "layerblock", "match", "input", "output", "module", "circuit",
+            "FIRRTL", "type", "public", "is", "invalid", "with",
+        ];
+
+        let mut source = String::from(
+"circuit Top :
+  module Top : @[Top.scala 1:1]
+    input clock : Clock
+");
+        for kw in keywords.iter() {
+            source.push_str(&format!("    wire {} : {{ a : UInt<1> }}\n", kw));
+            source.push_str(&format!("    {} is invalid\n", kw));
+            source.push_str(&format!("    {}.a <= clock\n", kw));
+            source.push_str(&format!("    {}[0] <- {} @[Top.scala 2:3]\n", kw, kw));
+        }
+        // Still statements when not followed by a connect
+        source.push_str("    wire reader : UInt<1>\n    reader <= clock\n    skip\n");
+
+        let ast = crate::parse_circuit(&source).expect("FAILED");
+        let stmts = match ast.modules[0].as_ref() {
+            rusty_firrtl::CircuitModule::Module(m) => &m.stmts,
+            _ => panic!("expected a module"),
+        };
+
+        fn root(expr: &rusty_firrtl::Expr) -> String {
+            let mut reference = match expr {
+                rusty_firrtl::Expr::Reference(r) => r,
+                _ => panic!("expected a reference"),
+            };
+            loop {
+                match reference {
+                    rusty_firrtl::Reference::Ref(Identifier::Name(name)) => return name.clone(),
+                    rusty_firrtl::Reference::RefDot(r, _) |
+                        rusty_firrtl::Reference::RefIdxInt(r, _) |
+                        rusty_firrtl::Reference::RefIdxExpr(r, _) => reference = r,
+                    _ => panic!("expected a named reference"),
+                }
+            }
+        }
+        let sinks: Vec<String> = stmts.iter().filter_map(|stmt| match stmt.as_ref() {
+            rusty_firrtl::Stmt::Invalidate(sink, _) |
+                rusty_firrtl::Stmt::Connect(sink, _, _) |
+                rusty_firrtl::Stmt::PartialConnect(sink, _, _) => Some(root(sink)),
+            _ => None,
+        }).collect();
+        let mut expected: Vec<String> = keywords.iter()
+            .flat_map(|kw| std::iter::repeat_n(kw.to_string(), 3))
+            .collect();
+        expected.push("reader".to_string());
+        assert_eq!(sinks, expected);
+        assert!(matches!(stmts.last().unwrap().as_ref(), rusty_firrtl::Stmt::Skip(_)));
+    }
+
+    #[test]
+    fn legacy_keywords_are_identifiers() {
+        let source =
+r#"
+node is = and(with, invalid)
+connect invalid, is
+"#;
+        let lexer = FIRRTLLexer::new_with_mode(source, SyntaxMode::Standard);
+        let parser = StmtsParser::new();
+        let ast = parser.parse(lexer).expect("FAILED");
+        println!("{:?}", ast);
+
+        assert_eq!(SyntaxMode::from_source("FIRRTL version 3.3.0\ncircuit Top :"), SyntaxMode::Standard);
+    }
+
+    #[test]
+    fn version_checks() {
+        let body =
+r#"circuit Top :
+  public module Top : @[Top.scala 1:7]
+    input clock : Clock
+    output out : UInt<8>
+    connect out, UInt<8>(0)
+"#;
+        let v4 = format!("FIRRTL version 4.0.0\n{}", body);
+        crate::parse_circuit_checked(&v4).expect("FAILED");
+
+        let v3 = format!("FIRRTL version 3.3.0\n{}", body);
+        crate::parse_circuit_checked(&v3).expect("FAILED");
+
+        let v2 = format!("FIRRTL version 3.2.0\n{}", body);
+        crate::parse_circuit(&v2).expect("FAILED");
+        match crate::parse_circuit_checked(&v2) {
+            Err(ParseError::User { error: LexicalError::UnsupportedVersion(err) }) => {
+                assert_eq!(err, VersionError::Requires("public".to_string(), Version(3, 3, 0)));
+                assert_eq!(err.to_string(), "construct `public` requires FIRRTL >= 3.3.0");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let legacy = "FIRRTL version 4.0.0\ncircuit Top :\n  module Top : @[Top.scala 1:7]\n    output out : UInt<8>\n    out <= UInt<8>(0)\n";
+        match crate::parse_circuit_checked(legacy) {
+            Err(ParseError::User { error: LexicalError::UnsupportedVersion(err) }) => {
+                assert_eq!(err.to_string(), "construct `<=` was removed in FIRRTL 3.0.0");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let intmodule =
+r#"FIRRTL version 3.1.0
+circuit Top :
+  intmodule ClockGate : @[Top.scala 1:1]
+    input in : Clock
+    input en : UInt<1>
+    output out : Clock
+    intrinsic = circt_clock_gate
+  module Top : @[Top.scala 2:1]
+    input clock : Clock
+"#;
+        crate::parse_circuit_checked(intmodule).expect("FAILED");
+        let expr = intmodule.replace("intmodule ClockGate", "module ClockGate")
+            .replace("intrinsic = circt_clock_gate", "connect out, intrinsic(circt_clock_gate : Clock, in, en)");
+        match crate::parse_circuit_checked(&expr) {
+            Err(ParseError::User { error: LexicalError::UnsupportedVersion(err) }) => {
+                assert_eq!(err, VersionError::Requires("intrinsic".to_string(), Version(4, 0, 0)));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        assert_eq!(declared_version("; generated\nFIRRTL version 3.1.0\ncircuit Top :"), Some(Version(3, 1, 0)));
+        assert_eq!(declared_version("FIRRTL version 3.1\ncircuit Top :"), None);
+        assert_eq!(declared_version("circuit Top :"), None);
+    }
+
+    #[test]
+    fn version_boundaries() {
+        // The first version with each construct, or the one that removed it
+        let added = [
+            (Token::Probe, Version(2, 0, 0)),
+            (Token::RWProbe, Version(2, 0, 0)),
+            (Token::ProbeType, Version(2, 0, 0)),
+            (Token::RWProbeType, Version(2, 0, 0)),
+            (Token::Define, Version(2, 0, 0)),
+            (Token::Force, Version(2, 0, 0)),
+            (Token::ForceInitial, Version(2, 0, 0)),
+            (Token::Release, Version(2, 0, 0)),
+            (Token::ReleaseInitial, Version(2, 0, 0)),
+            (Token::LeftEnumBracket, Version(2, 1, 0)),
+            (Token::Match, Version(2, 1, 0)),
+            (Token::Const, Version(3, 0, 0)),
+            (Token::Connect, Version(3, 0, 0)),
+            (Token::Invalidate, Version(3, 0, 0)),
+            (Token::RegReset, Version(3, 0, 0)),
+            (Token::IntegerType, Version(3, 1, 0)),
+            (Token::PropAssign, Version(3, 1, 0)),
+            (Token::IntModule, Version(3, 1, 0)),
+            (Token::Layer, Version(3, 2, 0)),
+            (Token::LayerBlock, Version(3, 2, 0)),
+            (Token::EnableLayer, Version(3, 2, 0)),
+            (Token::StringType, Version(3, 2, 0)),
+            (Token::BooleanType, Version(3, 2, 0)),
+            (Token::DoubleType, Version(3, 2, 0)),
+            (Token::PathType, Version(3, 2, 0)),
+            (Token::ListType, Version(3, 2, 0)),
+            (Token::AnyRefType, Version(3, 2, 0)),
+            (Token::InstType, Version(3, 2, 0)),
+            (Token::Path, Version(3, 2, 0)),
+            (Token::PropPrimOp("integer_add".to_string()), Version(3, 2, 0)),
+            (Token::Class, Version(3, 2, 0)),
+            (Token::ExtClass, Version(3, 2, 0)),
+            (Token::Object, Version(3, 2, 0)),
+            (Token::Public, Version(3, 3, 0)),
+            (Token::Type, Version(3, 3, 0)),
+            (Token::InstChoice, Version(4, 0, 0)),
+            (Token::Option, Version(4, 0, 0)),
+            (Token::Intrinsic, Version(4, 0, 0)),
+        ];
+        for (token, since) in added.iter() {
+            let before = match since {
+                Version(a, 0, 0) => Version(a - 1, 99, 99),
+                Version(a, b, _) => Version(*a, b - 1, 99),
+            };
+            assert!(matches!(check_token(token, before), Err(VersionError::Requires(_, v)) if v == *since),
+                "{:?} should require {:?}", token, since);
+            assert_eq!(check_token(token, *since), Ok(()), "{:?} should be allowed in {:?}", token, since);
+        }
+
+        let removed = [Token::LegacyConnect, Token::LegacyPartialConnect, Token::Is, Token::With];
+        for token in removed.iter() {
+            assert_eq!(check_token(token, Version(2, 99, 99)), Ok(()), "{:?} should be allowed before 3.0.0", token);
+            assert!(matches!(check_token(token, Version(3, 0, 0)), Err(VersionError::Removed(_, v)) if v == Version(3, 0, 0)),
+                "{:?} should be removed in 3.0.0", token);
+        }
+    }
+
     #[test]
     fn bug() -> Result<(), std::io::Error> {
         let source = r#"node _T_567 = asSInt(_T_566) @[generators/rocket-chip/src/main/scala/diplomacy/Parameters.scala 137:46]"#;
@@ -0,0 +1,100 @@
+use rusty_firrtl::{Parameter, Stmt};
+
+/// A `"..."` or `'...'` literal as it appears in the source, e.g. the message of a `printf`,
+/// together with the text it stands for once its escapes are decoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringLiteral {
+    /// The lexeme including its quotes and escapes, for re-emitting it unchanged
+    pub raw: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringLiteralError {
+    /// Not enclosed in matching `"` or `'`
+    Unquoted(String),
+    /// A backslash followed by a character that is not a known escape
+    InvalidEscape(char),
+    /// `\u` not followed by four hex digits naming a valid character
+    InvalidUnicode(String),
+}
+
+impl StringLiteral {
+    pub fn parse(raw: &str) -> Result<Self, StringLiteralError> {
+        Ok(Self { raw: raw.to_string(), value: decode(raw)? })
+    }
+
+    /// Like `parse`, for a `'...'` raw string whose only escape is `\'`
+    pub fn parse_raw(raw: &str) -> Result<Self, StringLiteralError> {
+        Ok(Self { raw: raw.to_string(), value: decode_raw(raw)? })
+    }
+}
+
+/// Strips the quotes of a string literal and replaces `\n`, `\t`, `\r`, `\"`, `\'`, `\\`
+/// and `\uXXXX` by the characters they stand for
+pub fn decode(raw: &str) -> Result<String, StringLiteralError> {
+    let body = ['"', '\'']
+        .iter()
+        .find_map(|q| raw.strip_prefix(*q).and_then(|s| s.strip_suffix(*q)))
+        .filter(|_| raw.len() >= 2)
+        .ok_or_else(|| StringLiteralError::Unquoted(raw.to_string()))?;
+
+    let mut value = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n')  => value.push('\n'),
+            Some('t')  => value.push('\t'),
+            Some('r')  => value.push('\r'),
+            Some('"')  => value.push('"'),
+            Some('\'') => value.push('\''),
+            Some('\\') => value.push('\\'),
+            Some('u')  => {
+                let digits: String = chars.by_ref().take(4).collect();
+                let c = Some(&digits)
+                    .filter(|d| d.len() == 4)
+                    .and_then(|d| u32::from_str_radix(d, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| StringLiteralError::InvalidUnicode(format!("\\u{}", digits)))?;
+                value.push(c);
+            }
+            Some(c) => return Err(StringLiteralError::InvalidEscape(c)),
+            None    => return Err(StringLiteralError::InvalidEscape('\\')),
+        }
+    }
+    Ok(value)
+}
+
+/// Strips the quotes of a `'...'` raw string and replaces `\'` by `'`,
+/// keeping every other backslash as it is
+pub fn decode_raw(raw: &str) -> Result<String, StringLiteralError> {
+    let body = raw.strip_prefix('\'')
+        .and_then(|s| s.strip_suffix('\''))
+        .filter(|_| raw.len() >= 2)
+        .ok_or_else(|| StringLiteralError::Unquoted(raw.to_string()))?;
+    Ok(body.replace("\\'", "'"))
+}
+
+/// The message of a `printf`, `assert`, `assume` or `cover`
+pub fn message(stmt: &Stmt) -> Option<Result<StringLiteral, StringLiteralError>> {
+    match stmt {
+        Stmt::Printf(_, _, _, msg, _, _) |
+            Stmt::Assert(_, _, _, _, msg, _) |
+            Stmt::Assume(_, _, _, _, msg, _) |
+            Stmt::Cover(_, _, _, _, msg, _) => Some(StringLiteral::parse(msg)),
+        _ => None,
+    }
+}
+
+/// The value of a string or raw string `parameter`
+pub fn parameter_value(param: &Parameter) -> Option<Result<StringLiteral, StringLiteralError>> {
+    match param {
+        Parameter::StringParam(_, s)    => Some(StringLiteral::parse(s)),
+        Parameter::RawStringParam(_, s) => Some(StringLiteral::parse_raw(s)),
+        _ => None,
+    }
+}
@@ -0,0 +1,96 @@
+use rusty_firrtl::{Circuit, CircuitModule, EnumVariant, Field, Identifier, Type, TypeAggregate, TypeProbe};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeAliasError {
+    /// A type refers to an alias that is not declared in the circuit
+    Undefined(Identifier),
+    /// An alias is (indirectly) defined in terms of itself
+    Cyclic(Identifier),
+}
+
+/// Type aliases declared at circuit scope by `type Foo = ...`
+#[derive(Debug, Default, Clone)]
+pub struct TypeAliases(HashMap<Identifier, Type>);
+
+impl TypeAliases {
+    pub fn from_circuit(circuit: &Circuit) -> Self {
+        let mut aliases = HashMap::new();
+        for module in circuit.modules.iter() {
+            if let CircuitModule::TypeAlias(alias) = module.as_ref() {
+                aliases.insert(alias.name.clone(), alias.tpe.clone());
+            }
+        }
+        Self(aliases)
+    }
+
+    /// The type an alias was declared with, which may itself refer to other aliases
+    pub fn get(&self, name: &Identifier) -> Option<&Type> {
+        self.0.get(name)
+    }
+
+    /// Replace every alias in `tpe`, including ones nested in bundles, vectors,
+    /// enums and probes, by the type it stands for
+    pub fn resolve(&self, tpe: &Type) -> Result<Type, TypeAliasError> {
+        self.resolve_type(tpe, &mut vec![])
+    }
+
+    fn resolve_type(&self, tpe: &Type, visiting: &mut Vec<Identifier>) -> Result<Type, TypeAliasError> {
+        match tpe {
+            Type::TypeAlias(name) => {
+                if visiting.contains(name) {
+                    return Err(TypeAliasError::Cyclic(name.clone()));
+                }
+                let aliased = self.get(name)
+                    .ok_or_else(|| TypeAliasError::Undefined(name.clone()))?;
+                visiting.push(name.clone());
+                let resolved = self.resolve_type(aliased, visiting)?;
+                visiting.pop();
+                Ok(resolved)
+            }
+            Type::TypeAggregate(ta) => {
+                Ok(Type::TypeAggregate(Box::new(self.resolve_aggregate(ta, visiting)?)))
+            }
+            Type::ConstTypeAggregate(ta) => {
+                Ok(Type::ConstTypeAggregate(Box::new(self.resolve_aggregate(ta, visiting)?)))
+            }
+            Type::TypeProbe(TypeProbe::Probe(inner, layer)) => {
+                Ok(Type::TypeProbe(TypeProbe::Probe(Box::new(self.resolve_type(inner, visiting)?), layer.clone())))
+            }
+            Type::TypeProbe(TypeProbe::RWProbe(inner, layer)) => {
+                Ok(Type::TypeProbe(TypeProbe::RWProbe(Box::new(self.resolve_type(inner, visiting)?), layer.clone())))
+            }
+            _ => Ok(tpe.clone()),
+        }
+    }
+
+    fn resolve_aggregate(&self, ta: &TypeAggregate, visiting: &mut Vec<Identifier>) -> Result<TypeAggregate, TypeAliasError> {
+        match ta {
+            TypeAggregate::Fields(fields) => {
+                let mut resolved = vec![];
+                for field in fields.iter() {
+                    let field = match field.as_ref() {
+                        Field::Straight(name, tpe) => Field::Straight(name.clone(), Box::new(self.resolve_type(tpe, visiting)?)),
+                        Field::Flipped(name, tpe)  => Field::Flipped(name.clone(), Box::new(self.resolve_type(tpe, visiting)?)),
+                    };
+                    resolved.push(Box::new(field));
+                }
+                Ok(TypeAggregate::Fields(Box::new(resolved)))
+            }
+            TypeAggregate::Array(tpe, len) => {
+                Ok(TypeAggregate::Array(Box::new(self.resolve_type(tpe, visiting)?), len.clone()))
+            }
+            TypeAggregate::Enum(variants) => {
+                let mut resolved = vec![];
+                for variant in variants.iter() {
+                    let tpe = match &variant.tpe {
+                        Some(tpe) => Some(Box::new(self.resolve_type(tpe, visiting)?)),
+                        None => None,
+                    };
+                    resolved.push(Box::new(EnumVariant::new(variant.name.clone(), tpe)));
+                }
+                Ok(TypeAggregate::Enum(Box::new(resolved)))
+            }
+        }
+    }
+}
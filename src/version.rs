@@ -0,0 +1,120 @@
+use crate::firrtl::VersionParser;
+use crate::lexer::{FIRRTLLexer, LexicalError, Spanned, SyntaxMode, Token, VersionError};
+use rusty_firrtl::Version;
+use std::iter::Peekable;
+
+/// The version in the `FIRRTL version a.b.c` header, if the circuit starts with one
+pub fn declared_version(source: &str) -> Option<Version> {
+    // `FIRRTL`, `version`, then the three numbers and the two dots between them
+    let header = FIRRTLLexer::new_with_mode(source, SyntaxMode::Standard).take(7);
+    VersionParser::new().parse(header).ok()
+}
+
+impl SyntaxMode {
+    /// Picks `Legacy` when the `FIRRTL version` header is older than 3.0.0 or missing
+    pub fn from_source(source: &str) -> Self {
+        match declared_version(source) {
+            Some(version) if version >= Version(3, 0, 0) => SyntaxMode::Standard,
+            _ => SyntaxMode::Legacy,
+        }
+    }
+}
+
+/// Whether `token` may appear in a circuit that declares `version`.
+/// Each group lists the entry of the spec's revision history that adds or removes it.
+pub fn check_token(token: &Token, version: Version) -> Result<(), VersionError> {
+    let (construct, since, until) = match token {
+        // 2.0.0: "Add probe types and statements"
+        Token::Probe                => ("Probe",            Version(2, 0, 0), None),
+        Token::RWProbe              => ("RWProbe",          Version(2, 0, 0), None),
+        Token::ProbeType            => ("probe",            Version(2, 0, 0), None),
+        Token::RWProbeType          => ("rwprobe",          Version(2, 0, 0), None),
+        Token::Define               => ("define",           Version(2, 0, 0), None),
+        Token::Force                => ("force",            Version(2, 0, 0), None),
+        Token::ForceInitial         => ("force_initial",    Version(2, 0, 0), None),
+        Token::Release              => ("release",          Version(2, 0, 0), None),
+        Token::ReleaseInitial       => ("release_initial",  Version(2, 0, 0), None),
+        // 2.1.0: "Add enumeration types and the match statement"
+        Token::LeftEnumBracket      => ("{|",               Version(2, 1, 0), None),
+        Token::Match                => ("match",            Version(2, 1, 0), None),
+        // 3.0.0: "Add const types"
+        Token::Const                => ("const",            Version(3, 0, 0), None),
+        // 3.0.0: "Replace `<=`, `<-`, `is invalid` and `reg ... with` by `connect`,
+        // `invalidate` and `regreset`"
+        Token::Connect              => ("connect",          Version(3, 0, 0), None),
+        Token::Invalidate           => ("invalidate",       Version(3, 0, 0), None),
+        Token::RegReset             => ("regreset",         Version(3, 0, 0), None),
+        Token::LegacyConnect        => ("<=",               Version(0, 0, 0), Some(Version(3, 0, 0))),
+        Token::LegacyPartialConnect => ("<-",               Version(0, 0, 0), Some(Version(3, 0, 0))),
+        Token::Is                   => ("is invalid",       Version(0, 0, 0), Some(Version(3, 0, 0))),
+        Token::With                 => ("with",             Version(0, 0, 0), Some(Version(3, 0, 0))),
+        // 3.1.0: "Add the Integer property type and propassign", "Add intrinsic modules"
+        Token::IntegerType          => ("Integer",          Version(3, 1, 0), None),
+        Token::PropAssign           => ("propassign",       Version(3, 1, 0), None),
+        Token::IntModule            => ("intmodule",        Version(3, 1, 0), None),
+        // 3.2.0: "Add layers"
+        Token::Layer                => ("layer",            Version(3, 2, 0), None),
+        Token::LayerBlock           => ("layerblock",       Version(3, 2, 0), None),
+        Token::EnableLayer          => ("enablelayer",      Version(3, 2, 0), None),
+        // 3.2.0: "Add classes, objects and the remaining property types and expressions"
+        Token::StringType           => ("String",           Version(3, 2, 0), None),
+        Token::BooleanType          => ("Boolean",          Version(3, 2, 0), None),
+        Token::DoubleType           => ("Double",           Version(3, 2, 0), None),
+        Token::PathType             => ("Path",             Version(3, 2, 0), None),
+        Token::ListType             => ("List",             Version(3, 2, 0), None),
+        Token::AnyRefType           => ("AnyRef",           Version(3, 2, 0), None),
+        Token::InstType             => ("Inst",             Version(3, 2, 0), None),
+        Token::Path                 => ("path",             Version(3, 2, 0), None),
+        Token::PropPrimOp(op)       => (op.as_str(),        Version(3, 2, 0), None),
+        Token::Class                => ("class",            Version(3, 2, 0), None),
+        Token::ExtClass             => ("extclass",         Version(3, 2, 0), None),
+        Token::Object               => ("object",           Version(3, 2, 0), None),
+        // 3.3.0: "Add public modules", "Add type aliases"
+        Token::Public               => ("public",           Version(3, 3, 0), None),
+        Token::Type                 => ("type",             Version(3, 3, 0), None),
+        // 4.0.0: "Add instance choices and option groups", "Add generic intrinsics"
+        Token::InstChoice           => ("instchoice",       Version(4, 0, 0), None),
+        Token::Option               => ("option",           Version(4, 0, 0), None),
+        Token::Intrinsic            => ("intrinsic",        Version(4, 0, 0), None),
+        _ => return Ok(()),
+    };
+
+    match until {
+        Some(until) if version >= until => Err(VersionError::Removed(construct.to_string(), until)),
+        _ if version < since => Err(VersionError::Requires(construct.to_string(), since)),
+        _ => Ok(()),
+    }
+}
+
+/// The tokens of a `FIRRTLLexer`, rejecting those of constructs that do not exist in FIRRTL `version`
+pub struct VersionCheck<'input> {
+    tokens: Peekable<FIRRTLLexer<'input>>,
+    version: Version,
+}
+
+impl<'input> VersionCheck<'input> {
+    pub fn new(lexer: FIRRTLLexer<'input>, version: Version) -> Self {
+        Self { tokens: lexer.keep_legacy_connects().peekable(), version }
+    }
+}
+
+impl<'input> Iterator for VersionCheck<'input> {
+    type Item = Spanned<Token, usize, LexicalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line, token, start) = match self.tokens.next()? {
+            Ok(spanned) => spanned,
+            Err(err) => return Some(Err(err)),
+        };
+        // Only `intrinsic(...)` is new in 4.0.0, the `intrinsic = name` of an `intmodule` is older
+        let intmodule_field = token == Token::Intrinsic &&
+            !matches!(self.tokens.peek(), Some(Ok((_, Token::LeftParenthesis, _))));
+        if intmodule_field {
+            return Some(Ok((line, token, start)));
+        }
+        match check_token(&token, self.version) {
+            Ok(()) => Some(Ok((line, token, start))),
+            Err(err) => Some(Err(LexicalError::UnsupportedVersion(err))),
+        }
+    }
+}